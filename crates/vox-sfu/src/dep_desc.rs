@@ -0,0 +1,166 @@
+//! Parser for the AV1 Dependency Descriptor that trails the fixed
+//! `MediaHeader` whenever `FLAG_HAS_DEP_DESC` is set (see `header.rs`).
+//!
+//! This is a compact, SFU-local wire format inspired by the RTP Dependency
+//! Descriptor extension (draft-ietf-avtext-framemarking-style), not a
+//! byte-for-byte copy of it — like the rest of this crate's wire structs, it
+//! mirrors only as much of the real thing as `forward_to_room` needs to make
+//! safe layer-dropping decisions for AV1 SVC without decoding the bitstream.
+//!
+//! Layout, immediately after the 22-byte `MediaHeader`:
+//! ```text
+//! byte 0:    [start_of_frame:1][end_of_frame:1][structure_present:1][template_id:5]
+//! bytes 1-2: frame_number (u16, big-endian)
+//! -- only if structure_present --
+//! byte 3:    [spatial_id:4][temporal_id:4]
+//! byte 4:    [is_switch_point:1][referenced_frame_count:7]
+//! bytes 5..: referenced_frame_count single-byte frame-diffs (frame_number - diff)
+//! ```
+
+/// One frame's place in the SVC dependency structure: which spatial/temporal
+/// layer it belongs to, which earlier frames it references, and whether a
+/// decoder can start fresh at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameDependency {
+    pub spatial_id: u8,
+    pub temporal_id: u8,
+    /// Frame numbers this frame's decode depends on, resolved from the
+    /// wire format's compact frame-diffs.
+    pub referenced_frames: Vec<u16>,
+    /// Whether a decoder (or this SFU) can safely treat this frame as a new
+    /// decode target — i.e. switch a subscriber to a different layer here
+    /// without risking a frame that references something never forwarded.
+    pub is_switch_point: bool,
+}
+
+/// A parsed AV1 Dependency Descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyDescriptor {
+    pub start_of_frame: bool,
+    pub end_of_frame: bool,
+    pub frame_number: u16,
+    pub template_id: u8,
+    /// Present only on frames that (re-)establish the template structure;
+    /// frames between them reuse the last-seen structure and carry `None`
+    /// here, same as real AV1 DD.
+    pub dependency: Option<FrameDependency>,
+}
+
+impl DependencyDescriptor {
+    /// Parse a dependency descriptor from the start of `data` (the bytes
+    /// immediately following the fixed `MediaHeader`), returning it
+    /// alongside whatever trails it (the encoded video payload).
+    pub fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        let first = *data.first()?;
+        let start_of_frame = first & 0b1000_0000 != 0;
+        let end_of_frame = first & 0b0100_0000 != 0;
+        let structure_present = first & 0b0010_0000 != 0;
+        let template_id = first & 0b0001_1111;
+
+        let rest = data.get(1..)?;
+        let frame_number = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+        let rest = rest.get(2..)?;
+
+        let (dependency, rest) = if structure_present {
+            let layer_byte = *rest.first()?;
+            let spatial_id = layer_byte >> 4;
+            let temporal_id = layer_byte & 0x0F;
+
+            let switch_byte = *rest.get(1)?;
+            let is_switch_point = switch_byte & 0b1000_0000 != 0;
+            let ref_count = (switch_byte & 0b0111_1111) as usize;
+
+            let rest = rest.get(2..)?;
+            let diffs = rest.get(..ref_count)?;
+            let referenced_frames = diffs
+                .iter()
+                .map(|&diff| frame_number.wrapping_sub(diff as u16))
+                .collect();
+            let rest = &rest[ref_count..];
+
+            (
+                Some(FrameDependency {
+                    spatial_id,
+                    temporal_id,
+                    referenced_frames,
+                    is_switch_point,
+                }),
+                rest,
+            )
+        } else {
+            (None, rest)
+        };
+
+        Some((
+            DependencyDescriptor {
+                start_of_frame,
+                end_of_frame,
+                frame_number,
+                template_id,
+                dependency,
+            },
+            rest,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mandatory_fields_only() {
+        let mut buf = vec![0u8; 3];
+        buf[0] = 0b1100_0101; // start+end of frame, no structure, template_id=5
+        buf[1..3].copy_from_slice(&42u16.to_be_bytes());
+        buf.extend_from_slice(b"payload");
+
+        let (desc, rest) = DependencyDescriptor::parse(&buf).unwrap();
+        assert!(desc.start_of_frame);
+        assert!(desc.end_of_frame);
+        assert_eq!(desc.template_id, 5);
+        assert_eq!(desc.frame_number, 42);
+        assert!(desc.dependency.is_none());
+        assert_eq!(rest, b"payload");
+    }
+
+    #[test]
+    fn parse_with_template_structure_and_references() {
+        let mut buf = vec![0u8; 3];
+        buf[0] = 0b1010_0011; // start_of_frame, structure_present, template_id=3
+        buf[1..3].copy_from_slice(&100u16.to_be_bytes());
+        buf.push(0x21); // spatial_id=2, temporal_id=1
+        buf.push(0b1000_0010); // is_switch_point, ref_count=2
+        buf.push(1); // references frame 99
+        buf.push(3); // references frame 97
+        buf.extend_from_slice(b"payload");
+
+        let (desc, rest) = DependencyDescriptor::parse(&buf).unwrap();
+        assert!(desc.start_of_frame);
+        assert!(!desc.end_of_frame);
+        assert_eq!(desc.frame_number, 100);
+        let dep = desc.dependency.unwrap();
+        assert_eq!(dep.spatial_id, 2);
+        assert_eq!(dep.temporal_id, 1);
+        assert!(dep.is_switch_point);
+        assert_eq!(dep.referenced_frames, vec![99, 97]);
+        assert_eq!(rest, b"payload");
+    }
+
+    #[test]
+    fn parse_truncated_mandatory_fields() {
+        assert!(DependencyDescriptor::parse(&[0u8; 2]).is_none());
+    }
+
+    #[test]
+    fn parse_truncated_template_structure() {
+        let mut buf = vec![0u8; 3];
+        buf[0] = 0b0010_0000; // structure_present, no start/end, template_id=0
+        buf[1..3].copy_from_slice(&10u16.to_be_bytes());
+        buf.push(0x00); // spatial_id=0, temporal_id=0
+        buf.push(0b0000_0011); // not a switch point, claims 3 references
+        buf.push(1); // only one diff byte present, not the claimed 3
+
+        assert!(DependencyDescriptor::parse(&buf).is_none());
+    }
+}
@@ -1,10 +1,44 @@
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
 use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 
-use crate::header::MediaHeader;
+use crate::dep_desc::DependencyDescriptor;
+use crate::header::{
+    AuthFrame, KeyframeObjectPrefix, MediaHeader, STREAM_KIND_FEEDBACK, STREAM_KIND_KEYFRAME_OBJECT,
+};
 use crate::state::SharedState;
 use crate::tls;
 
+/// How often each connection's `quinn::Connection::stats()` are sampled for
+/// the RTT/loss metrics gauges (layer selection itself is driven by
+/// `run_feedback_stream`, not this probe).
+const STATS_PROBE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Per-layer target bitrates, highest quality first, mirroring
+/// `video::SIMULCAST_LAYERS` on the publishing client. Duplicated here
+/// rather than shared, matching this codebase's existing split between the
+/// two crates (e.g. `MediaHeader`).
+const LAYER_BITRATES_BPS: [u32; 3] = [2_000_000, 800_000, 350_000];
+
+/// Upper bound on a keyframe object's total size (`MediaHeader` + encoded
+/// payload), matched to the largest single keyframe chunk any simulcast
+/// layer should ever produce. Guards `read_to_end` against a misbehaving or
+/// malicious peer opening a stream and never sending a FIN.
+const MAX_KEYFRAME_OBJECT_SIZE: usize = 2 * 1024 * 1024;
+
+/// QUIC application error codes the SFU closes a connection with during
+/// admission, so a well-behaved client (or an operator reading a packet
+/// capture) can tell rejection reasons apart instead of guessing from a
+/// single generic code.
+const AUTH_ERROR_MALFORMED: u32 = 1;
+const AUTH_ERROR_UNKNOWN_TOKEN: u32 = 2;
+const AUTH_ERROR_EXPIRED_TOKEN: u32 = 3;
+const AUTH_ERROR_ALREADY_CONNECTED: u32 = 4;
+
 /// Run the QUIC media endpoint: accept connections, authenticate, forward datagrams.
 pub async fn run(bind_addr: String, state: SharedState, cancel: CancellationToken) {
     let (server_config, _cert_der) = tls::generate_self_signed();
@@ -56,8 +90,8 @@ pub async fn run(bind_addr: String, state: SharedState, cancel: CancellationToke
 
 /// Handle a single QUIC connection: authenticate via first datagram, then forward.
 async fn handle_connection(conn: quinn::Connection, state: SharedState, cancel: CancellationToken) {
-    // Auth: first datagram must be the media token (UTF-8)
-    let token_data = tokio::select! {
+    // Auth: first datagram must be an AuthFrame (media token + supported codecs)
+    let auth_data = tokio::select! {
         result = conn.read_datagram() => {
             match result {
                 Ok(data) => data,
@@ -70,42 +104,99 @@ async fn handle_connection(conn: quinn::Connection, state: SharedState, cancel:
         _ = cancel.cancelled() => return,
     };
 
-    let token = match std::str::from_utf8(&token_data) {
-        Ok(t) => t.to_string(),
-        Err(_) => {
-            tracing::debug!("invalid UTF-8 in auth token");
-            conn.close(1u32.into(), b"invalid token");
+    let auth = match AuthFrame::parse(&auth_data) {
+        Some(a) => a,
+        None => {
+            tracing::debug!("malformed auth frame");
+            crate::metrics::auth_failure("malformed");
+            conn.close(AUTH_ERROR_MALFORMED.into(), b"invalid auth frame");
             return;
         }
     };
+    let token = auth.token;
 
-    // Look up token -> (room_id, user_id)
+    // Validate the token (unknown/expired) and bind the connection into its
+    // user session (rejecting a second concurrent connection) in one
+    // write-lock critical section, so nothing else can observe or act on
+    // this token between the two checks.
     let (room_id, user_id) = {
-        let st = state.read().await;
-        match st.token_index.get(&token) {
-            Some(&ids) => ids,
+        let mut st = state.write().await;
+        let ids = match st.token_index.get_mut(&token) {
+            Some(entry) => {
+                if entry.first_used_at.is_none() && entry.issued_at.elapsed() > crate::state::TOKEN_EXPIRY {
+                    tracing::debug!("expired media token");
+                    crate::metrics::auth_failure("expired");
+                    conn.close(AUTH_ERROR_EXPIRED_TOKEN.into(), b"expired token");
+                    return;
+                }
+                entry.first_used_at.get_or_insert_with(std::time::Instant::now);
+                entry.ids()
+            }
             None => {
                 tracing::debug!("unknown media token");
-                conn.close(1u32.into(), b"unknown token");
+                crate::metrics::auth_failure("unknown_token");
+                conn.close(AUTH_ERROR_UNKNOWN_TOKEN.into(), b"unknown token");
                 return;
             }
-        }
-    };
+        };
+        let (room_id, user_id) = ids;
 
-    // Store connection in user session
-    {
-        let mut st = state.write().await;
-        if let Some(room) = st.rooms.get_mut(&room_id) {
-            if let Some(session) = room.users.get_mut(&user_id) {
-                session.connection = Some(conn.clone());
-            }
+        let Some(session) = st
+            .rooms
+            .get_mut(&room_id)
+            .and_then(|room| room.users.get_mut(&user_id))
+        else {
+            tracing::debug!("token authenticated but user session is gone");
+            crate::metrics::auth_failure("unknown_token");
+            conn.close(AUTH_ERROR_UNKNOWN_TOKEN.into(), b"unknown token");
+            return;
+        };
+        if session.connection.is_some() {
+            tracing::debug!("user {} already has a live connection", user_id);
+            crate::metrics::auth_failure("already_connected");
+            conn.close(AUTH_ERROR_ALREADY_CONNECTED.into(), b"already connected");
+            return;
         }
-    }
+        session.connection = Some(conn.clone());
+        session.supported_codecs = auth.codecs;
+
+        ids
+    };
 
     tracing::info!(
         "user {} authenticated in room {} via QUIC",
         user_id, room_id
     );
+    crate::metrics::connection_opened(room_id);
+
+    // Sample connection stats for the RTT/loss metrics gauges.
+    tokio::spawn(monitor_bandwidth(conn.clone(), room_id, user_id, cancel.clone()));
+
+    // Apply this peer's delay-based bandwidth estimates (reported over its
+    // feedback stream) to its selected simulcast layer.
+    let feedback_targets = {
+        let st = state.read().await;
+        st.rooms
+            .get(&room_id)
+            .and_then(|room| room.users.get(&user_id))
+            .map(|session| {
+                (
+                    Arc::clone(&session.selected_video_layer),
+                    Arc::clone(&session.target_bitrate_bps),
+                )
+            })
+    };
+    if let Some((selected_layer, target_bitrate_bps)) = feedback_targets {
+        tokio::spawn(accept_uni_streams(
+            conn.clone(),
+            room_id,
+            user_id,
+            selected_layer,
+            target_bitrate_bps,
+            state.clone(),
+            cancel.clone(),
+        ));
+    }
 
     // Forwarding loop
     loop {
@@ -113,7 +204,7 @@ async fn handle_connection(conn: quinn::Connection, state: SharedState, cancel:
             result = conn.read_datagram() => {
                 match result {
                     Ok(data) => {
-                        forward_datagram(&data, room_id, user_id, &state).await;
+                        forward_to_room(&data, room_id, user_id, &state).await;
                     }
                     Err(e) => {
                         tracing::debug!("connection closed for user {}: {}", user_id, e);
@@ -134,14 +225,18 @@ async fn handle_connection(conn: quinn::Connection, state: SharedState, cancel:
             }
         }
     }
+    crate::metrics::connection_closed(room_id);
 }
 
-/// Forward a datagram to all other connected users in the same room.
-async fn forward_datagram(data: &[u8], room_id: u32, sender_id: u32, state: &SharedState) {
+/// Forward a datagram to all other connected users in the same room,
+/// selecting per-receiver video layers so each peer only gets the quality
+/// its current downlink estimate can sustain.
+pub(crate) async fn forward_to_room(data: &[u8], room_id: u32, sender_id: u32, state: &SharedState) {
     let header = match MediaHeader::parse(data) {
         Some(h) => h,
         None => {
             tracing::trace!("datagram too short to parse header");
+            crate::metrics::datagram_dropped("unparseable");
             return;
         }
     };
@@ -152,18 +247,389 @@ async fn forward_datagram(data: &[u8], room_id: u32, sender_id: u32, state: &Sha
             "header mismatch: expected room={} user={}, got room={} user={}",
             room_id, sender_id, header.room_id, header.user_id
         );
+        crate::metrics::datagram_dropped("header_mismatch");
         return;
     }
 
+    let is_video = header.media_type == crate::header::MEDIA_TYPE_VIDEO;
+    let media_type_label = if is_video { "video" } else { "audio" };
+    // Parsed once up front (same for every subscriber) when the publisher is
+    // sending AV1 SVC: tells us which spatial/temporal layer this frame
+    // belongs to and whether it's safe to switch a subscriber's forwarded
+    // layer at.
+    let dep_desc = if is_video && header.has_dep_desc() {
+        DependencyDescriptor::parse(&data[crate::header::HEADER_SIZE..]).map(|(d, _)| d)
+    } else {
+        None
+    };
     let data = Bytes::copy_from_slice(data);
+    let mut fanout = 0usize;
     let st = state.read().await;
     if let Some(room) = st.rooms.get(&room_id) {
+        if let Some(ref recording_tx) = room.recording_tx {
+            let unit = crate::state::RecordedUnit {
+                user_id: sender_id,
+                media_type: header.media_type,
+                is_keyframe: header.is_keyframe(),
+                timestamp: header.timestamp,
+                payload: data.slice(crate::header::HEADER_SIZE..),
+            };
+            let _ = recording_tx.send(unit);
+        }
         for (uid, session) in &room.users {
-            if *uid != sender_id {
-                if let Some(ref peer_conn) = session.connection {
-                    let _ = peer_conn.send_datagram(data.clone());
+            if *uid == sender_id {
+                continue;
+            }
+            let Some(ref peer_conn) = session.connection else {
+                continue;
+            };
+            if is_video {
+                let target_layer = session.selected_video_layer.load(Ordering::Relaxed);
+                match dep_desc.as_ref() {
+                    Some(desc) => {
+                        if !forward_svc_frame(session, &header, sender_id, desc, target_layer) {
+                            continue;
+                        }
+                    }
+                    None => {
+                        // Plain simulcast (or an unparseable/absent Dependency
+                        // Descriptor): each layer is its own independent
+                        // stream, so only an exact match is ever safe — unless
+                        // the sender never produces more than one layer (RTMP
+                        // ingest), in which case it's the only video this
+                        // subscriber will ever get and must be forwarded
+                        // regardless of which layer it's degraded to.
+                        if !header.is_single_layer() && header.spatial_id != target_layer {
+                            continue;
+                        }
+                    }
+                }
+            } else if !session.supported_codecs.is_empty()
+                && !session.supported_codecs.contains(&header.codec_id)
+            {
+                // This subscriber never advertised support for the sender's
+                // audio codec; forwarding it would just be decoded as noise.
+                continue;
+            }
+            if peer_conn.send_datagram(data.clone()).is_ok() {
+                fanout += 1;
+            }
+        }
+    }
+    drop(st);
+    crate::metrics::datagram_forwarded(room_id, media_type_label, data.len(), fanout);
+}
+
+/// Decide whether an AV1 SVC frame (one carrying a Dependency Descriptor)
+/// from `sender_id` should be forwarded to one subscriber, and update that
+/// subscriber's per-publisher `svc_layer_ceiling` entry as needed: dropping
+/// down to fewer enhancement layers applies immediately since it never
+/// breaks decode, but climbing to more layers only takes effect once a
+/// frame marks itself a decode-target switch point, so a subscriber is
+/// never handed an enhancement frame without every layer it depends on.
+/// Forwarding every layer at or below the ceiling also transitively carries
+/// every frame a forwarded frame depends on, since AV1 SVC layers only ever
+/// reference the same or a lower spatial/temporal layer.
+///
+/// `target_layer` is `selected_video_layer`'s simulcast index (0 = highest
+/// quality, higher = more degraded — the opposite direction from an SVC
+/// spatial_id, where 0 is the base layer and higher means more enhancement
+/// data on top of it), so it's inverted against `NUM_VIDEO_LAYERS` before
+/// being compared to a frame's spatial_id.
+fn forward_svc_frame(
+    session: &crate::state::UserSession,
+    header: &MediaHeader,
+    sender_id: u32,
+    desc: &DependencyDescriptor,
+    target_layer: u8,
+) -> bool {
+    let (frame_spatial_id, is_switch_point) = match &desc.dependency {
+        Some(dep) => (dep.spatial_id, dep.is_switch_point),
+        // No fresh template structure on this frame — fall back to the
+        // header's own spatial_id, and don't treat it as a safe retarget
+        // point since we can't confirm what it depends on.
+        None => (header.spatial_id, false),
+    };
+
+    let svc_target = (crate::state::NUM_VIDEO_LAYERS - 1).saturating_sub(target_layer);
+
+    let mut ceilings = session.svc_layer_ceiling.lock().unwrap();
+    let ceiling = *ceilings.entry(sender_id).or_insert(svc_target);
+    let new_ceiling = if svc_target < ceiling || (svc_target > ceiling && is_switch_point) {
+        svc_target
+    } else {
+        ceiling
+    };
+    ceilings.insert(sender_id, new_ceiling);
+    drop(ceilings);
+
+    frame_spatial_id <= new_ceiling
+}
+
+/// Periodically sample `quinn::Connection::stats()` for one connection and
+/// publish its RTT/loss to the metrics gauges.
+async fn monitor_bandwidth(
+    conn: quinn::Connection,
+    room_id: u32,
+    user_id: u32,
+    cancel: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(STATS_PROBE_INTERVAL) => {}
+            _ = cancel.cancelled() => break,
+        }
+        if conn.close_reason().is_some() {
+            break;
+        }
+
+        let stats = conn.stats();
+        crate::metrics::connection_stats(
+            room_id,
+            user_id,
+            stats.path.rtt.as_millis() as i64,
+            stats.path.lost_packets as i64,
+        );
+    }
+}
+
+/// Accept every unidirectional stream this peer opens for the life of the
+/// connection and dispatch it by its leading stream-kind byte: the one
+/// long-lived bandwidth feedback stream, or any number of short-lived
+/// keyframe-object streams (`TransportMode::Hybrid` publishers only).
+async fn accept_uni_streams(
+    conn: quinn::Connection,
+    room_id: u32,
+    sender_id: u32,
+    selected_layer: Arc<AtomicU8>,
+    target_bitrate_bps: Arc<AtomicU32>,
+    state: SharedState,
+    cancel: CancellationToken,
+) {
+    loop {
+        let mut recv = tokio::select! {
+            result = conn.accept_uni() => {
+                match result {
+                    Ok(r) => r,
+                    Err(e) => {
+                        tracing::debug!("uni stream accept ended for user {}: {}", sender_id, e);
+                        break;
+                    }
+                }
+            }
+            _ = cancel.cancelled() => break,
+        };
+
+        let mut kind = [0u8; 1];
+        if recv.read_exact(&mut kind).await.is_err() {
+            continue;
+        }
+
+        match kind[0] {
+            STREAM_KIND_FEEDBACK => {
+                tokio::spawn(run_feedback_stream(
+                    recv,
+                    Arc::clone(&selected_layer),
+                    Arc::clone(&target_bitrate_bps),
+                    cancel.clone(),
+                ));
+            }
+            STREAM_KIND_KEYFRAME_OBJECT => {
+                tokio::spawn(handle_keyframe_object_stream(
+                    recv,
+                    room_id,
+                    sender_id,
+                    state.clone(),
+                ));
+            }
+            other => {
+                tracing::debug!("unknown uni stream kind {} from user {}", other, sender_id);
+            }
+        }
+    }
+}
+
+/// Apply each reported target bitrate — from the client's delay-based
+/// congestion controller — to the highest simulcast layer that fits the
+/// budget. `LAYER_BITRATES_BPS.len() - 1` (the most-degraded layer) is the
+/// floor: a peer never gets dropped further no matter how low the estimate.
+async fn run_feedback_stream(
+    mut recv: quinn::RecvStream,
+    selected_layer: Arc<AtomicU8>,
+    target_bitrate_bps: Arc<AtomicU32>,
+    cancel: CancellationToken,
+) {
+    let mut buf = [0u8; 4];
+    loop {
+        tokio::select! {
+            result = recv.read_exact(&mut buf) => {
+                if result.is_err() {
+                    break;
                 }
             }
+            _ = cancel.cancelled() => break,
         }
+
+        let target_bps = u32::from_be_bytes(buf);
+        target_bitrate_bps.store(target_bps, Ordering::Relaxed);
+        let chosen_layer = LAYER_BITRATES_BPS
+            .iter()
+            .position(|&layer_bps| layer_bps <= target_bps)
+            .unwrap_or(LAYER_BITRATES_BPS.len() - 1) as u8;
+        selected_layer.store(chosen_layer, Ordering::Relaxed);
+    }
+}
+
+/// Read one keyframe object to completion from its own reliable stream and
+/// forward it to subscribers. Unlike datagram forwarding there's no fixed
+/// MTU to respect, so the whole object is read before it's relayed.
+async fn handle_keyframe_object_stream(
+    mut recv: quinn::RecvStream,
+    room_id: u32,
+    sender_id: u32,
+    state: SharedState,
+) {
+    let data = match recv.read_to_end(MAX_KEYFRAME_OBJECT_SIZE).await {
+        Ok(d) => d,
+        Err(e) => {
+            tracing::debug!("failed to read keyframe object from user {}: {}", sender_id, e);
+            return;
+        }
+    };
+
+    let Some((prefix, payload)) = KeyframeObjectPrefix::parse(&data) else {
+        tracing::trace!("keyframe object too short to parse from user {}", sender_id);
+        crate::metrics::datagram_dropped("unparseable");
+        return;
+    };
+
+    if prefix.header.room_id != room_id || prefix.header.user_id != sender_id {
+        tracing::warn!(
+            "keyframe object header mismatch: expected room={} user={}, got room={} user={}",
+            room_id, sender_id, prefix.header.room_id, prefix.header.user_id
+        );
+        crate::metrics::datagram_dropped("header_mismatch");
+        return;
+    }
+
+    forward_keyframe_object(
+        prefix.group_id,
+        &prefix.header,
+        Bytes::copy_from_slice(payload),
+        sender_id,
+        room_id,
+        &state,
+    )
+    .await;
+}
+
+/// Forward a keyframe object to every subscriber currently selected for the
+/// sender's layer, each over its own freshly opened unidirectional stream.
+/// Eligibility mirrors `forward_to_room`'s video gate: plain simulcast uses
+/// exact spatial_id equality, while an AV1 SVC sender (`header.has_dep_desc()`)
+/// goes through `forward_svc_frame`'s ceiling logic instead, since an SVC
+/// base-layer keyframe is still required by every subscriber regardless of
+/// its selected (inverted-index) layer.
+/// If a subscriber still has an older keyframe stream in flight for the same
+/// (sender, layer), it is abandoned via `reset_stream` as soon as the new one
+/// starts, so bandwidth isn't wasted finishing an object a fresher keyframe
+/// has already made stale.
+async fn forward_keyframe_object(
+    group_id: u32,
+    header: &MediaHeader,
+    payload: Bytes,
+    sender_id: u32,
+    room_id: u32,
+    state: &SharedState,
+) {
+    let is_video = header.media_type == crate::header::MEDIA_TYPE_VIDEO;
+    let key = (sender_id, header.spatial_id);
+    let object = {
+        use bytes::{BufMut, BytesMut};
+        let mut buf = BytesMut::with_capacity(1 + crate::header::KEYFRAME_OBJECT_PREFIX_SIZE + payload.len());
+        buf.put_u8(STREAM_KIND_KEYFRAME_OBJECT);
+        buf.put_u32(group_id);
+        buf.put_slice(&header.encode());
+        buf.extend_from_slice(&payload);
+        buf.freeze()
+    };
+    let priority = group_id as i32;
+
+    let subscribers: Vec<_> = {
+        let st = state.read().await;
+        let Some(room) = st.rooms.get(&room_id) else {
+            return;
+        };
+        room.users
+            .iter()
+            .filter(|(uid, session)| **uid != sender_id && session.connection.is_some())
+            .filter(|(_, session)| {
+                if is_video {
+                    if header.has_dep_desc() {
+                        // AV1 SVC: reconcile with `forward_svc_frame`'s
+                        // ceiling logic rather than treating spatial_id as a
+                        // simulcast layer index. A keyframe is always a safe
+                        // decode-target switch point, so the ceiling jumps
+                        // straight to the subscriber's current target
+                        // instead of waiting for a marked switch point on
+                        // some later datagram.
+                        let target_layer = session.selected_video_layer.load(Ordering::Relaxed);
+                        let svc_target = (crate::state::NUM_VIDEO_LAYERS - 1).saturating_sub(target_layer);
+                        session.svc_layer_ceiling.lock().unwrap().insert(sender_id, svc_target);
+                        header.spatial_id <= svc_target
+                    } else {
+                        session.selected_video_layer.load(Ordering::Relaxed) == header.spatial_id
+                    }
+                } else {
+                    session.supported_codecs.is_empty()
+                        || session.supported_codecs.contains(&header.codec_id)
+                }
+            })
+            .map(|(_, session)| {
+                (
+                    session.connection.clone().unwrap(),
+                    Arc::clone(&session.keyframe_streams),
+                )
+            })
+            .collect()
+    };
+
+    for (peer_conn, keyframe_streams) in subscribers {
+        let object = object.clone();
+        tokio::spawn(async move {
+            let mut send = match peer_conn.open_uni().await {
+                Ok(s) => s,
+                Err(e) => {
+                    tracing::debug!("failed to open keyframe stream to subscriber: {}", e);
+                    return;
+                }
+            };
+            let _ = send.set_priority(priority);
+            let handle = Arc::new(tokio::sync::Mutex::new(send));
+
+            let old = {
+                let mut streams = keyframe_streams.lock().await;
+                streams.insert(key, Arc::clone(&handle))
+            };
+            // Best-effort: if the prior object for this (sender, layer) is
+            // still being written, abandon it now that it's stale; if it's
+            // already finished this is a harmless no-op.
+            if let Some(old) = old {
+                tokio::spawn(async move {
+                    let _ = old.lock().await.reset(0u32.into());
+                });
+            }
+
+            // Write in chunks, releasing the lock between them, so a newer
+            // keyframe's task can reset this stream part-way through.
+            for chunk in object.chunks(16 * 1024) {
+                let mut send = handle.lock().await;
+                if let Err(e) = send.write_all(chunk).await {
+                    tracing::debug!("failed to write keyframe object to subscriber: {}", e);
+                    return;
+                }
+            }
+            let _ = handle.lock().await.finish();
+        });
     }
 }
@@ -1,12 +1,35 @@
 /// Size of the fixed media frame header in bytes.
 pub const HEADER_SIZE: usize = 22;
 
+/// Discriminator byte sent as the first byte of every unidirectional QUIC
+/// stream a client opens, so the SFU can tell a long-lived bandwidth
+/// feedback stream apart from a one-shot keyframe object stream. Mirrors
+/// vox-media's `quic.rs`.
+pub const STREAM_KIND_FEEDBACK: u8 = 0;
+pub const STREAM_KIND_KEYFRAME_OBJECT: u8 = 1;
+
+/// Size of a keyframe-object stream's prefix after its stream-kind byte has
+/// already been consumed by the accept loop: a `u32` group id, then the
+/// fixed `MediaHeader`.
+pub const KEYFRAME_OBJECT_PREFIX_SIZE: usize = 4 + HEADER_SIZE;
+
+// Media type values (byte 1) — mirrors vox-media's quic.rs.
+pub const MEDIA_TYPE_AUDIO: u8 = 0;
+pub const MEDIA_TYPE_VIDEO: u8 = 1;
+
 // Flag bits (byte 3)
 pub const FLAG_KEYFRAME: u8 = 0b1000_0000;
 pub const FLAG_END_OF_FRAME: u8 = 0b0100_0000;
 pub const FLAG_FEC: u8 = 0b0010_0000;
 pub const FLAG_MARKER: u8 = 0b0001_0000;
 pub const FLAG_HAS_DEP_DESC: u8 = 0b0000_1000;
+/// Set on video frames from a source that only ever produces one layer (RTMP
+/// ingest, which transcodes to a single VP9 stream rather than simulcasting).
+/// `forward_to_room`'s plain-simulcast gate skips the `spatial_id ==
+/// selected_video_layer` check for these, since holding out for an exact
+/// layer match that will never exist at anything but layer 0 would otherwise
+/// blackhole video for any subscriber GCC has degraded below the base layer.
+pub const FLAG_SINGLE_LAYER: u8 = 0b0000_0100;
 
 /// Parsed media frame header (22 bytes fixed).
 #[derive(Debug, Clone)]
@@ -47,6 +70,25 @@ impl MediaHeader {
         })
     }
 
+    /// Serialize the header into 22 bytes (big-endian), mirroring
+    /// vox-media's `MediaHeader::encode`. Used when re-emitting a header
+    /// this process parsed itself, e.g. forwarding a keyframe object to a
+    /// new subscriber stream.
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0] = self.version;
+        buf[1] = self.media_type;
+        buf[2] = self.codec_id;
+        buf[3] = self.flags;
+        buf[4..8].copy_from_slice(&self.room_id.to_be_bytes());
+        buf[8..12].copy_from_slice(&self.user_id.to_be_bytes());
+        buf[12..16].copy_from_slice(&self.sequence.to_be_bytes());
+        buf[16..20].copy_from_slice(&self.timestamp.to_be_bytes());
+        buf[20] = (self.spatial_id << 4) | (self.temporal_id & 0x0F);
+        buf[21] = if self.dtx { 0x80 } else { 0 };
+        buf
+    }
+
     pub fn is_keyframe(&self) -> bool {
         self.flags & FLAG_KEYFRAME != 0
     }
@@ -58,6 +100,57 @@ impl MediaHeader {
     pub fn has_dep_desc(&self) -> bool {
         self.flags & FLAG_HAS_DEP_DESC != 0
     }
+
+    pub fn is_single_layer(&self) -> bool {
+        self.flags & FLAG_SINGLE_LAYER != 0
+    }
+}
+
+/// Prefix of an inbound keyframe-object stream (after its stream-kind byte
+/// has already been consumed by the accept loop): the publisher's monotonic
+/// group id for this layer's keyframes, plus the usual `MediaHeader`. The
+/// rest of the stream, up to its FIN, is the raw encoded payload.
+pub struct KeyframeObjectPrefix {
+    pub group_id: u32,
+    pub header: MediaHeader,
+}
+
+impl KeyframeObjectPrefix {
+    /// Parse the prefix from the start of a fully-read keyframe-object
+    /// stream, returning it alongside the remaining payload bytes.
+    pub fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        if data.len() < KEYFRAME_OBJECT_PREFIX_SIZE {
+            return None;
+        }
+        let group_id = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let header = MediaHeader::parse(&data[4..])?;
+        Some((
+            KeyframeObjectPrefix { group_id, header },
+            &data[KEYFRAME_OBJECT_PREFIX_SIZE..],
+        ))
+    }
+}
+
+/// Parsed auth datagram: the media token plus which audio codecs this peer
+/// can decode, in priority order. This is the very first datagram a client
+/// sends on a new connection. Mirrors vox-media's `encode_auth_frame`.
+///
+/// Wire layout: `[token_len: u8][token bytes][codec_count: u8][codec ids]`.
+pub struct AuthFrame {
+    pub token: String,
+    pub codecs: Vec<u8>,
+}
+
+impl AuthFrame {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let token_len = *data.first()? as usize;
+        let rest = data.get(1..)?;
+        let token = std::str::from_utf8(rest.get(..token_len)?).ok()?.to_string();
+        let rest = rest.get(token_len..)?;
+        let codec_count = *rest.first()? as usize;
+        let codecs = rest.get(1..1 + codec_count)?.to_vec();
+        Some(AuthFrame { token, codecs })
+    }
 }
 
 #[cfg(test)]
@@ -93,8 +186,55 @@ mod tests {
         assert!(h.dtx);
     }
 
+    #[test]
+    fn parse_single_layer_flag() {
+        let mut buf = [0u8; 22];
+        buf[3] = FLAG_SINGLE_LAYER;
+        let h = MediaHeader::parse(&buf).unwrap();
+        assert!(h.is_single_layer());
+        assert!(!h.is_keyframe());
+    }
+
     #[test]
     fn parse_too_short() {
         assert!(MediaHeader::parse(&[0u8; 10]).is_none());
     }
+
+    #[test]
+    fn parse_auth_frame_roundtrip() {
+        let mut buf = vec![5u8]; // token_len
+        buf.extend_from_slice(b"abcde");
+        buf.push(2); // codec_count
+        buf.extend_from_slice(&[1, 2]);
+
+        let auth = AuthFrame::parse(&buf).unwrap();
+        assert_eq!(auth.token, "abcde");
+        assert_eq!(auth.codecs, vec![1, 2]);
+    }
+
+    #[test]
+    fn parse_auth_frame_truncated() {
+        assert!(AuthFrame::parse(&[5u8, b'a', b'b']).is_none());
+    }
+
+    #[test]
+    fn parse_keyframe_object_prefix() {
+        let mut buf = vec![0u8; 4 + HEADER_SIZE];
+        buf[0..4].copy_from_slice(&7u32.to_be_bytes()); // group_id
+        buf[4] = 1; // version
+        buf[5] = 1; // video
+        buf[6] = 2; // VP9
+        buf[7] = FLAG_KEYFRAME | FLAG_END_OF_FRAME;
+        buf.extend_from_slice(b"payload");
+
+        let (prefix, payload) = KeyframeObjectPrefix::parse(&buf).unwrap();
+        assert_eq!(prefix.group_id, 7);
+        assert!(prefix.header.is_keyframe());
+        assert_eq!(payload, b"payload");
+    }
+
+    #[test]
+    fn parse_keyframe_object_prefix_too_short() {
+        assert!(KeyframeObjectPrefix::parse(&[0u8; 10]).is_none());
+    }
 }
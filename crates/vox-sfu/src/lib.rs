@@ -1,18 +1,19 @@
+mod dep_desc;
+mod endpoint;
+mod header;
+mod metrics;
+mod recording;
+mod rtmp;
+mod state;
+mod tls;
+mod transcode;
+
 use pyo3::prelude::*;
-use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
-/// A media room tracked by the SFU.
-struct Room {
-    room_id: u32,
-    users: HashMap<u32, UserSession>,
-}
-
-/// A user's media session within a room.
-struct UserSession {
-    user_id: u32,
-    token: String,
-}
+use recording::{RecordingCommand, RecordingEvent, RecordingEventQueue};
 
 /// The Selective Forwarding Unit for QUIC media transport.
 ///
@@ -22,8 +23,13 @@ struct UserSession {
 #[pyclass]
 struct SFU {
     bind_addr: String,
-    rooms: Arc<Mutex<HashMap<u32, Room>>>,
-    running: Arc<Mutex<bool>>,
+    rtmp_bind_addr: Option<String>,
+    metrics_bind_addr: Option<String>,
+    state: state::SharedState,
+    cancel: Option<CancellationToken>,
+    rt_handle: Option<std::thread::JoinHandle<()>>,
+    rec_cmd_tx: Option<mpsc::UnboundedSender<RecordingCommand>>,
+    rec_events: RecordingEventQueue,
 }
 
 #[pymethods]
@@ -32,114 +38,180 @@ impl SFU {
     ///
     /// Args:
     ///     bind: Address to bind the QUIC listener (e.g. "0.0.0.0:4443")
+    ///     rtmp_bind: Optional address to bind an RTMP ingest listener
+    ///         (e.g. "0.0.0.0:1935"), letting OBS/ffmpeg publish into a room.
+    ///     metrics_bind: Optional address to serve Prometheus `/metrics` on
+    ///         (e.g. "0.0.0.0:9090"). Requires the crate's `metrics` feature;
+    ///         ignored otherwise.
     #[new]
-    fn new(bind: &str) -> Self {
+    #[pyo3(signature = (bind, rtmp_bind=None, metrics_bind=None))]
+    fn new(bind: &str, rtmp_bind: Option<String>, metrics_bind: Option<String>) -> Self {
         SFU {
             bind_addr: bind.to_string(),
-            rooms: Arc::new(Mutex::new(HashMap::new())),
-            running: Arc::new(Mutex::new(false)),
+            rtmp_bind_addr: rtmp_bind,
+            metrics_bind_addr: metrics_bind,
+            state: state::new_shared(),
+            cancel: None,
+            rt_handle: None,
+            rec_cmd_tx: None,
+            rec_events: Arc::new(Mutex::new(std::collections::VecDeque::new())),
         }
     }
 
-    /// Start the QUIC listener on a background thread.
-    fn start(&self) -> PyResult<()> {
-        let mut running = self.running.lock().unwrap();
-        if *running {
+    /// Start the QUIC listener (and RTMP ingest, if configured) on a
+    /// background thread.
+    fn start(&mut self) -> PyResult<()> {
+        if self.cancel.is_some() {
             return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
                 "SFU is already running",
             ));
         }
-        *running = true;
+
+        let cancel = CancellationToken::new();
+        self.cancel = Some(cancel.clone());
 
         let bind_addr = self.bind_addr.clone();
-        let rooms = Arc::clone(&self.rooms);
+        let rtmp_bind_addr = self.rtmp_bind_addr.clone();
+        let metrics_bind_addr = self.metrics_bind_addr.clone();
+        let state = self.state.clone();
+
+        let (rec_cmd_tx, rec_cmd_rx) = mpsc::unbounded_channel();
+        self.rec_cmd_tx = Some(rec_cmd_tx);
+        let rec_events = self.rec_events.clone();
 
-        std::thread::spawn(move || {
+        let handle = std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(async move {
-                tracing_subscriber::fmt::init();
+                tracing_subscriber::fmt::try_init().ok();
                 tracing::info!("SFU starting on {}", bind_addr);
 
-                // TODO: set up quinn QUIC endpoint, accept connections,
-                // authenticate via media_token, forward datagrams between
-                // room participants based on SVC layer decisions
-                let _ = rooms;
-                loop {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                let quic_cancel = cancel.clone();
+                let quic_state = state.clone();
+                let quic_task = tokio::spawn(endpoint::run(bind_addr, quic_state, quic_cancel));
+
+                let rtmp_task = rtmp_bind_addr.map(|addr| {
+                    let rtmp_cancel = cancel.clone();
+                    let rtmp_state = state.clone();
+                    tokio::spawn(rtmp::run(addr, rtmp_state, rtmp_cancel))
+                });
+
+                let rec_cancel = cancel.clone();
+                let rec_state = state.clone();
+                let rec_task = tokio::spawn(recording::run(rec_cmd_rx, rec_state, rec_events, rec_cancel));
+
+                let metrics_task = metrics_bind_addr.map(|addr| {
+                    tokio::spawn(metrics::run(addr, cancel.clone()))
+                });
+
+                let _ = quic_task.await;
+                if let Some(task) = rtmp_task {
+                    let _ = task.await;
+                }
+                let _ = rec_task.await;
+                if let Some(task) = metrics_task {
+                    let _ = task.await;
                 }
             });
         });
 
+        self.rt_handle = Some(handle);
         Ok(())
     }
 
     /// Stop the SFU.
-    fn stop(&self) -> PyResult<()> {
-        let mut running = self.running.lock().unwrap();
-        *running = false;
+    fn stop(&mut self) -> PyResult<()> {
+        if let Some(cancel) = self.cancel.take() {
+            cancel.cancel();
+        }
         tracing::info!("SFU stopping");
-        // TODO: signal the background runtime to shut down
+        if let Some(handle) = self.rt_handle.take() {
+            let _ = handle.join();
+        }
         Ok(())
     }
 
     /// Create a new media room.
     fn add_room(&self, room_id: u32) -> PyResult<()> {
-        let mut rooms = self.rooms.lock().unwrap();
-        rooms.insert(
-            room_id,
-            Room {
-                room_id,
-                users: HashMap::new(),
-            },
-        );
+        self.state.blocking_write().add_room(room_id);
         Ok(())
     }
 
     /// Remove a media room and disconnect all participants.
     fn remove_room(&self, room_id: u32) -> PyResult<()> {
-        let mut rooms = self.rooms.lock().unwrap();
-        rooms.remove(&room_id);
+        self.state.blocking_write().remove_room(room_id);
         Ok(())
     }
 
     /// Admit a user to a room with their media token.
     fn admit_user(&self, room_id: u32, user_id: u32, token: &str) -> PyResult<()> {
-        let mut rooms = self.rooms.lock().unwrap();
-        let room = rooms.get_mut(&room_id).ok_or_else(|| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+        let mut st = self.state.blocking_write();
+        if !st.rooms.contains_key(&room_id) {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
                 "Room {} does not exist",
                 room_id
-            ))
-        })?;
-        room.users.insert(
-            user_id,
-            UserSession {
-                user_id,
-                token: token.to_string(),
-            },
-        );
+            )));
+        }
+        st.admit_user(room_id, user_id, token);
         Ok(())
     }
 
     /// Remove a user from a room.
     fn remove_user(&self, room_id: u32, user_id: u32) -> PyResult<()> {
-        let mut rooms = self.rooms.lock().unwrap();
-        if let Some(room) = rooms.get_mut(&room_id) {
-            room.users.remove(&user_id);
-        }
+        self.state.blocking_write().remove_user(room_id, user_id);
         Ok(())
     }
 
     /// Get the list of user IDs in a room.
     fn get_room_users(&self, room_id: u32) -> PyResult<Vec<u32>> {
-        let rooms = self.rooms.lock().unwrap();
-        let room = rooms.get(&room_id).ok_or_else(|| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-                "Room {} does not exist",
-                room_id
-            ))
-        })?;
-        Ok(room.users.keys().cloned().collect())
+        self.state
+            .blocking_read()
+            .get_room_users(room_id)
+            .ok_or_else(|| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Room {} does not exist",
+                    room_id
+                ))
+            })
+    }
+
+    /// Start recording a room's media to fragmented MP4 files under `path`
+    /// (one file per participant). Requires the SFU to be running.
+    fn start_recording(&self, room_id: u32, path: &str) -> PyResult<()> {
+        self.send_rec_cmd(RecordingCommand::Start {
+            room_id,
+            path: path.into(),
+        })
+    }
+
+    /// Stop recording a room.
+    fn stop_recording(&self, room_id: u32) -> PyResult<()> {
+        self.send_rec_cmd(RecordingCommand::Stop { room_id })
+    }
+
+    /// Poll for the next recording event: `(event_type, room_id, detail)`,
+    /// or `None` if no events are pending.
+    fn poll_recording_event(&self) -> Option<(String, u32, String)> {
+        let event = self.rec_events.lock().ok()?.pop_front()?;
+        Some(match event {
+            RecordingEvent::Started { room_id, path } => ("started".into(), room_id, path),
+            RecordingEvent::Stopped { room_id, path, elapsed_secs } => {
+                ("stopped".into(), room_id, format!("{path},elapsed={elapsed_secs:.1}"))
+            }
+            RecordingEvent::Error { room_id, reason } => ("error".into(), room_id, reason),
+        })
+    }
+}
+
+impl SFU {
+    fn send_rec_cmd(&self, cmd: RecordingCommand) -> PyResult<()> {
+        match &self.rec_cmd_tx {
+            Some(tx) => tx.send(cmd).map_err(|_| {
+                PyErr::new::<pyo3::exceptions::PyRuntimeError, _>("SFU is not running")
+            }),
+            None => Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                "SFU is not running",
+            )),
+        }
     }
 }
 
@@ -0,0 +1,250 @@
+//! Optional Prometheus metrics for monitoring a running SFU.
+//!
+//! Gated behind the `metrics` cargo feature so the `prometheus`/`hyper`
+//! dependencies (and the scrape HTTP server) are opt-in. Call sites in
+//! `endpoint.rs`/`state.rs` call the free functions below unconditionally;
+//! with the feature disabled every one of them compiles away to nothing, so
+//! no `#[cfg(...)]` needs to leak into the instrumented code itself.
+
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "metrics")]
+mod imp {
+    use once_cell::sync::Lazy;
+    use prometheus::{
+        Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+        Registry, TextEncoder,
+    };
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use tokio_util::sync::CancellationToken;
+
+    static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+    pub static ROOM_COUNT: Lazy<IntGauge> = Lazy::new(|| {
+        register_gauge("vox_sfu_room_count", "Number of rooms currently active")
+    });
+
+    pub static ACTIVE_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+        register_gauge_vec(
+            "vox_sfu_active_connections",
+            "Active authenticated QUIC connections, by room",
+            &["room_id"],
+        )
+    });
+
+    pub static AUTH_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+        register_counter_vec(
+            "vox_sfu_auth_failures_total",
+            "Connection attempts that failed authentication, by reason",
+            &["reason"],
+        )
+    });
+
+    pub static DATAGRAMS_FORWARDED: Lazy<IntCounterVec> = Lazy::new(|| {
+        register_counter_vec(
+            "vox_sfu_datagrams_forwarded_total",
+            "Datagrams passed through the forwarding path (including those no peer received), by room and media type",
+            &["room_id", "media_type"],
+        )
+    });
+
+    pub static BYTES_FORWARDED: Lazy<IntCounterVec> = Lazy::new(|| {
+        register_counter_vec(
+            "vox_sfu_bytes_forwarded_total",
+            "Bytes forwarded (payload + header, counted once per datagram, not per recipient), by room",
+            &["room_id"],
+        )
+    });
+
+    pub static DATAGRAMS_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+        register_counter_vec(
+            "vox_sfu_datagrams_dropped_total",
+            "Datagrams dropped before forwarding, by reason",
+            &["reason"],
+        )
+    });
+
+    pub static FORWARD_FANOUT: Lazy<Histogram> = Lazy::new(|| {
+        let h = Histogram::with_opts(
+            HistogramOpts::new(
+                "vox_sfu_forward_fanout_size",
+                "Number of peers a single forwarded datagram fanned out to",
+            )
+            .buckets(vec![0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0]),
+        )
+        .unwrap();
+        REGISTRY.register(Box::new(h.clone())).unwrap();
+        h
+    });
+
+    pub static CONNECTION_RTT_MS: Lazy<IntGaugeVec> = Lazy::new(|| {
+        register_gauge_vec(
+            "vox_sfu_connection_rtt_ms",
+            "Smoothed RTT per connection, from quinn::Connection::stats()",
+            &["room_id", "user_id"],
+        )
+    });
+
+    pub static CONNECTION_LOST_PACKETS: Lazy<IntGaugeVec> = Lazy::new(|| {
+        register_gauge_vec(
+            "vox_sfu_connection_lost_packets",
+            "Cumulative lost packets per connection, from quinn::Connection::stats()",
+            &["room_id", "user_id"],
+        )
+    });
+
+    fn register_gauge(name: &str, help: &str) -> IntGauge {
+        let g = IntGauge::new(name, help).unwrap();
+        REGISTRY.register(Box::new(g.clone())).unwrap();
+        g
+    }
+
+    fn register_gauge_vec(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+        let g = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+        REGISTRY.register(Box::new(g.clone())).unwrap();
+        g
+    }
+
+    fn register_counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+        let c = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+        REGISTRY.register(Box::new(c.clone())).unwrap();
+        c
+    }
+
+    /// Serve `/metrics` in Prometheus text exposition format until cancelled.
+    pub async fn run(bind_addr: String, cancel: CancellationToken) {
+        let addr: SocketAddr = match bind_addr.parse() {
+            Ok(a) => a,
+            Err(e) => {
+                tracing::warn!("invalid metrics bind address {}: {}", bind_addr, e);
+                return;
+            }
+        };
+
+        let make_svc = hyper::service::make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(hyper::service::service_fn(serve))
+        });
+
+        let server = hyper::Server::bind(&addr).serve(make_svc);
+        tracing::info!("metrics endpoint listening on {}", addr);
+
+        tokio::select! {
+            result = server => {
+                if let Err(e) = result {
+                    tracing::warn!("metrics server error: {}", e);
+                }
+            }
+            _ = cancel.cancelled() => {
+                tracing::info!("metrics endpoint shutting down");
+            }
+        }
+    }
+
+    async fn serve(
+        _req: hyper::Request<hyper::Body>,
+    ) -> Result<hyper::Response<hyper::Body>, Infallible> {
+        let metric_families = REGISTRY.gather();
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&metric_families, &mut buf).unwrap();
+        Ok(hyper::Response::new(hyper::Body::from(buf)))
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod imp {
+    use tokio_util::sync::CancellationToken;
+
+    pub async fn run(_bind_addr: String, _cancel: CancellationToken) {}
+}
+
+/// Start the `/metrics` scrape endpoint. No-op when the `metrics` feature is
+/// disabled.
+pub async fn run(bind_addr: String, cancel: CancellationToken) {
+    imp::run(bind_addr, cancel).await;
+}
+
+/// Set the current number of active rooms.
+pub fn set_room_count(count: i64) {
+    #[cfg(feature = "metrics")]
+    imp::ROOM_COUNT.set(count);
+    #[cfg(not(feature = "metrics"))]
+    let _ = count;
+}
+
+/// Record a connection authenticating successfully into `room_id`.
+pub fn connection_opened(room_id: u32) {
+    #[cfg(feature = "metrics")]
+    imp::ACTIVE_CONNECTIONS
+        .with_label_values(&[&room_id.to_string()])
+        .inc();
+    #[cfg(not(feature = "metrics"))]
+    let _ = room_id;
+}
+
+/// Record a connection that was open in `room_id` closing.
+pub fn connection_closed(room_id: u32) {
+    #[cfg(feature = "metrics")]
+    imp::ACTIVE_CONNECTIONS
+        .with_label_values(&[&room_id.to_string()])
+        .dec();
+    #[cfg(not(feature = "metrics"))]
+    let _ = room_id;
+}
+
+/// Record a connection attempt that failed authentication, with a short
+/// reason label (e.g. `"malformed"`, `"unknown_token"`, `"expired"`,
+/// `"already_connected"`).
+pub fn auth_failure(reason: &str) {
+    #[cfg(feature = "metrics")]
+    imp::AUTH_FAILURES.with_label_values(&[reason]).inc();
+    #[cfg(not(feature = "metrics"))]
+    let _ = reason;
+}
+
+/// Record a datagram dropped before it could be forwarded, with a short
+/// reason label (e.g. `"unparseable"`, `"header_mismatch"`).
+pub fn datagram_dropped(reason: &str) {
+    #[cfg(feature = "metrics")]
+    imp::DATAGRAMS_DROPPED.with_label_values(&[reason]).inc();
+    #[cfg(not(feature = "metrics"))]
+    let _ = reason;
+}
+
+/// Record one datagram forwarded to `fanout` peers in `room_id`.
+pub fn datagram_forwarded(room_id: u32, media_type: &str, bytes: usize, fanout: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        let room = room_id.to_string();
+        imp::DATAGRAMS_FORWARDED
+            .with_label_values(&[&room, media_type])
+            .inc();
+        imp::BYTES_FORWARDED
+            .with_label_values(&[&room])
+            .inc_by(bytes as u64);
+        imp::FORWARD_FANOUT.observe(fanout as f64);
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = (room_id, media_type, bytes, fanout);
+    }
+}
+
+/// Record the latest `quinn::Connection::stats()` sample for one connection.
+pub fn connection_stats(room_id: u32, user_id: u32, rtt_ms: i64, lost_packets: i64) {
+    #[cfg(feature = "metrics")]
+    {
+        let room = room_id.to_string();
+        let user = user_id.to_string();
+        imp::CONNECTION_RTT_MS
+            .with_label_values(&[&room, &user])
+            .set(rtt_ms);
+        imp::CONNECTION_LOST_PACKETS
+            .with_label_values(&[&room, &user])
+            .set(lost_packets);
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = (room_id, user_id, rtt_ms, lost_packets);
+    }
+}
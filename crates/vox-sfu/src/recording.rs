@@ -0,0 +1,646 @@
+//! Server-side room recording to fragmented MP4.
+//!
+//! While recording is active for a room, `endpoint::forward_to_room` taps
+//! every forwarded frame into a per-room channel. This module demuxes that
+//! stream by `user_id`, accumulates samples into ISO BMFF fragments
+//! (`moof`+`mdat`, one fragment per video keyframe — mirroring moq-rs's
+//! `Source`/`Fragment` split), and writes them to disk so the result is
+//! playable and seekable without post-processing.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::header::{MEDIA_TYPE_AUDIO, MEDIA_TYPE_VIDEO};
+use crate::state::{RecordedUnit, SharedState};
+
+/// Commands accepted by the recording manager, mirroring the
+/// `MediaCommand` pattern vox-media uses for its client-side runtime.
+pub enum RecordingCommand {
+    Start { room_id: u32, path: PathBuf },
+    Stop { room_id: u32 },
+}
+
+/// Events emitted back to Python, mirroring `MediaEvent`.
+pub enum RecordingEvent {
+    Started { room_id: u32, path: String },
+    Stopped { room_id: u32, path: String, elapsed_secs: f64 },
+    Error { room_id: u32, reason: String },
+}
+
+pub type RecordingEventQueue = Arc<Mutex<VecDeque<RecordingEvent>>>;
+
+fn push_event(queue: &RecordingEventQueue, event: RecordingEvent) {
+    if let Ok(mut q) = queue.lock() {
+        q.push_back(event);
+    }
+}
+
+/// Drives recording commands until cancelled, spawning one writer task per
+/// active room.
+pub async fn run(
+    mut cmd_rx: mpsc::UnboundedReceiver<RecordingCommand>,
+    state: SharedState,
+    events: RecordingEventQueue,
+    cancel: CancellationToken,
+) {
+    let mut active: HashMap<u32, CancellationToken> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            cmd = cmd_rx.recv() => {
+                match cmd {
+                    None => break,
+                    Some(RecordingCommand::Start { room_id, path }) => {
+                        if active.contains_key(&room_id) {
+                            push_event(&events, RecordingEvent::Error {
+                                room_id,
+                                reason: "recording already active for this room".into(),
+                            });
+                            continue;
+                        }
+                        let (tx, rx) = mpsc::unbounded_channel();
+                        {
+                            let mut st = state.write().await;
+                            match st.rooms.get_mut(&room_id) {
+                                Some(room) => room.recording_tx = Some(tx),
+                                None => {
+                                    push_event(&events, RecordingEvent::Error {
+                                        room_id,
+                                        reason: format!("room {} does not exist", room_id),
+                                    });
+                                    continue;
+                                }
+                            }
+                        }
+                        let room_cancel = cancel.child_token();
+                        active.insert(room_id, room_cancel.clone());
+                        let events = events.clone();
+                        let path_str = path.display().to_string();
+                        tokio::spawn(async move {
+                            let started = Instant::now();
+                            push_event(&events, RecordingEvent::Started { room_id, path: path_str.clone() });
+                            if let Err(e) = record_room(room_id, path, rx, room_cancel).await {
+                                push_event(&events, RecordingEvent::Error { room_id, reason: e.to_string() });
+                            }
+                            push_event(&events, RecordingEvent::Stopped {
+                                room_id,
+                                path: path_str,
+                                elapsed_secs: started.elapsed().as_secs_f64(),
+                            });
+                        });
+                    }
+                    Some(RecordingCommand::Stop { room_id }) => {
+                        if let Some(room_cancel) = active.remove(&room_id) {
+                            room_cancel.cancel();
+                        }
+                        let mut st = state.write().await;
+                        if let Some(room) = st.rooms.get_mut(&room_id) {
+                            room.recording_tx = None;
+                        }
+                    }
+                }
+            }
+            _ = cancel.cancelled() => break,
+        }
+    }
+}
+
+/// Demux one room's tapped frames by `user_id` and write each user's track
+/// to its own fragmented MP4 file, `room{room_id}-user{user_id}.mp4`, inside
+/// the `base_path` directory (created if missing).
+async fn record_room(
+    room_id: u32,
+    base_path: PathBuf,
+    mut rx: mpsc::UnboundedReceiver<RecordedUnit>,
+    cancel: CancellationToken,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    tokio::fs::create_dir_all(&base_path).await.ok();
+    let mut tracks: HashMap<u32, FragmentedMp4Writer> = HashMap::new();
+
+    loop {
+        let unit = tokio::select! {
+            unit = rx.recv() => match unit {
+                Some(u) => u,
+                None => break,
+            },
+            _ = cancel.cancelled() => break,
+        };
+
+        let writer = match tracks.get_mut(&unit.user_id) {
+            Some(w) => w,
+            None => {
+                let file_path = base_path.join(format!("room{room_id}-user{}.mp4", unit.user_id));
+                let writer = FragmentedMp4Writer::create(file_path).await?;
+                tracks.entry(unit.user_id).or_insert(writer)
+            }
+        };
+        writer.push(&unit).await?;
+    }
+
+    for (_, writer) in tracks {
+        writer.finish().await?;
+    }
+    Ok(())
+}
+
+/// Track IDs used for the two tracks every recording file declares in its
+/// `moov`. Declared unconditionally (even if a user never sends video) so
+/// the `moov` init segment — and the `trun`/`tfhd` track references inside
+/// each `moof` — never change shape mid-recording.
+const AUDIO_TRACK_ID: u32 = 1;
+const VIDEO_TRACK_ID: u32 = 2;
+
+/// The SFU doesn't know a publisher's coded frame size ahead of a `moov`
+/// being written (and `RecordedUnit` doesn't carry it), so the video
+/// track's `tkhd`/sample-entry dimensions are advisory only, as they are in
+/// most fragmented-MP4 writers — a real decoder reads the actual coded size
+/// out of the VP9 bitstream, not out of the container.
+const VIDEO_WIDTH: u16 = 1280;
+const VIDEO_HEIGHT: u16 = 720;
+
+/// Minimal fragmented MP4 (ISO BMFF) writer: `ftyp` + a `moov` init segment
+/// declaring a fixed Opus audio track and VP9 video track (the project's
+/// only two media codecs — see `quic::CODEC_OPUS`/`quic::CODEC_VP9`),
+/// followed by one `moof`+`mdat` pair per fragment. A new fragment starts at
+/// every video keyframe, and whenever ~1s of audio samples has accumulated
+/// (with or without video), so the file is seekable by keyframe without a
+/// separate index pass.
+struct FragmentedMp4Writer {
+    file: File,
+    sequence_number: u32,
+    pending_samples: Vec<Sample>,
+    first_timestamp: Option<u32>,
+}
+
+struct Sample {
+    payload: Bytes,
+    timestamp_ms: u32,
+    media_type: u8,
+}
+
+impl FragmentedMp4Writer {
+    async fn create(path: PathBuf) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut file = File::create(&path).await?;
+        file.write_all(&ftyp_box()).await?;
+        file.write_all(&moov_box()).await?;
+        tracing::info!("recording {} started", path.display());
+        Ok(FragmentedMp4Writer {
+            file,
+            sequence_number: 0,
+            pending_samples: Vec::new(),
+            first_timestamp: None,
+        })
+    }
+
+    async fn push(&mut self, unit: &RecordedUnit) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let base = *self.first_timestamp.get_or_insert(unit.timestamp);
+        // MediaHeader.timestamp is an RTP-style 48kHz clock for audio and a
+        // 90kHz clock for video; both are stamped here as milliseconds
+        // relative to this track's first sample, matching `moov`'s 1kHz
+        // `mdhd`/`trex` timescale.
+        let clock_hz: u32 = if unit.media_type == MEDIA_TYPE_VIDEO { 90_000 } else { 48_000 };
+        let timestamp_ms = (unit.timestamp.wrapping_sub(base) as u64 * 1000 / clock_hz as u64) as u32;
+
+        let has_pending_video = self
+            .pending_samples
+            .iter()
+            .any(|s| s.media_type == MEDIA_TYPE_VIDEO);
+        let should_flush = unit.media_type == MEDIA_TYPE_VIDEO && unit.is_keyframe && has_pending_video;
+        if should_flush {
+            self.write_fragment().await?;
+        }
+
+        self.pending_samples.push(Sample {
+            payload: unit.payload.clone(),
+            timestamp_ms,
+            media_type: unit.media_type,
+        });
+
+        // Audio never carries a keyframe flag to trigger on, so cap fragment
+        // length at ~1s of audio samples (at 20ms/frame, 50 samples), whether
+        // or not video samples are pending too.
+        let pending_audio = self
+            .pending_samples
+            .iter()
+            .filter(|s| s.media_type == MEDIA_TYPE_AUDIO)
+            .count();
+        if pending_audio >= 50 {
+            self.write_fragment().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_fragment(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.pending_samples.is_empty() {
+            return Ok(());
+        }
+        self.sequence_number += 1;
+        let samples = std::mem::take(&mut self.pending_samples);
+        let (moof, mdat) = build_fragment(self.sequence_number, &samples);
+        self.file.write_all(&moof).await?;
+        self.file.write_all(&mdat).await?;
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.write_fragment().await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Build the static `ftyp` box that must open every ISO BMFF file.
+fn ftyp_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_slice(b"isom"); // major brand
+    body.put_u32(512); // minor version
+    body.put_slice(b"isomiso5dash");
+    wrap_box(b"ftyp", &body)
+}
+
+/// Build the one-time `moov` init segment: an `mvhd`, one `trak` per track,
+/// and an `mvex` (with one `trex` per track) marking the file as fragmented.
+/// Without this, a demuxer has no `stsd` codec config to initialize a
+/// decoder with and no way to tell the file is fragmented at all.
+fn moov_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put(mvhd_box());
+    body.put(trak_box(AUDIO_TRACK_ID, false));
+    body.put(trak_box(VIDEO_TRACK_ID, true));
+    body.put(mvex_box());
+    wrap_box(b"moov", &body)
+}
+
+fn mvhd_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    body.put_u32(0); // creation_time
+    body.put_u32(0); // modification_time
+    body.put_u32(1000); // timescale: milliseconds
+    body.put_u32(0); // duration: unknown, fragments carry it
+    body.put_u32(0x0001_0000); // rate: 1.0
+    body.put_u16(0x0100); // volume: 1.0
+    body.put_u16(0); // reserved
+    body.put_u32(0); // reserved
+    body.put_u32(0); // reserved
+    put_unity_matrix(&mut body);
+    for _ in 0..6 {
+        body.put_u32(0); // pre_defined
+    }
+    body.put_u32(3); // next_track_ID
+    wrap_box(b"mvhd", &body)
+}
+
+fn put_unity_matrix(body: &mut BytesMut) {
+    for v in [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        body.put_u32(v);
+    }
+}
+
+fn trak_box(track_id: u32, is_video: bool) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put(tkhd_box(track_id, is_video));
+    body.put(mdia_box(is_video));
+    wrap_box(b"trak", &body)
+}
+
+fn tkhd_box(track_id: u32, is_video: bool) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(0x0000_0007); // version/flags: enabled|in_movie|in_preview
+    body.put_u32(0); // creation_time
+    body.put_u32(0); // modification_time
+    body.put_u32(track_id);
+    body.put_u32(0); // reserved
+    body.put_u32(0); // duration
+    body.put_u32(0); // reserved
+    body.put_u32(0); // reserved
+    body.put_u16(0); // layer
+    body.put_u16(0); // alternate_group
+    body.put_u16(if is_video { 0 } else { 0x0100 }); // volume
+    body.put_u16(0); // reserved
+    put_unity_matrix(&mut body);
+    if is_video {
+        body.put_u32((VIDEO_WIDTH as u32) << 16);
+        body.put_u32((VIDEO_HEIGHT as u32) << 16);
+    } else {
+        body.put_u32(0);
+        body.put_u32(0);
+    }
+    wrap_box(b"tkhd", &body)
+}
+
+fn mdia_box(is_video: bool) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put(mdhd_box());
+    body.put(hdlr_box(is_video));
+    body.put(minf_box(is_video));
+    wrap_box(b"mdia", &body)
+}
+
+fn mdhd_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    body.put_u32(0); // creation_time
+    body.put_u32(0); // modification_time
+    body.put_u32(1000); // timescale: milliseconds, matches trun durations
+    body.put_u32(0); // duration
+    body.put_u16(0x55c4); // language: "und"
+    body.put_u16(0); // pre_defined
+    wrap_box(b"mdhd", &body)
+}
+
+fn hdlr_box(is_video: bool) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    body.put_u32(0); // pre_defined
+    body.put_slice(if is_video { b"vide" } else { b"soun" });
+    body.put_u32(0);
+    body.put_u32(0);
+    body.put_u32(0); // reserved
+    body.put_slice(if is_video { b"VideoHandler\0" } else { b"SoundHandler\0" });
+    wrap_box(b"hdlr", &body)
+}
+
+fn minf_box(is_video: bool) -> Bytes {
+    let mut body = BytesMut::new();
+    if is_video {
+        body.put(vmhd_box());
+    } else {
+        body.put(smhd_box());
+    }
+    body.put(dinf_box());
+    body.put(stbl_box(is_video));
+    wrap_box(b"minf", &body)
+}
+
+fn vmhd_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(1); // version/flags
+    body.put_u16(0); // graphicsmode
+    body.put_u16(0); // opcolor
+    body.put_u16(0);
+    body.put_u16(0);
+    wrap_box(b"vmhd", &body)
+}
+
+fn smhd_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    body.put_u16(0); // balance
+    body.put_u16(0); // reserved
+    wrap_box(b"smhd", &body)
+}
+
+fn dinf_box() -> Bytes {
+    let mut url = BytesMut::new();
+    url.put_u32(1); // version/flags: self-contained (no location URI)
+    let url = wrap_box(b"url ", &url);
+
+    let mut dref = BytesMut::new();
+    dref.put_u32(0); // version/flags
+    dref.put_u32(1); // entry_count
+    dref.put(url);
+    let dref = wrap_box(b"dref", &dref);
+
+    wrap_box(b"dinf", &dref)
+}
+
+fn stbl_box(is_video: bool) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put(if is_video { vp09_stsd_box() } else { opus_stsd_box() });
+    // Empty sample tables: sample-to-time/chunk/size/offset are all carried
+    // per fragment in `moof`/`trun` instead.
+    body.put(empty_table_box(b"stts", false));
+    body.put(empty_table_box(b"stsc", false));
+    body.put(empty_table_box(b"stsz", true));
+    body.put(empty_table_box(b"stco", false));
+    wrap_box(b"stbl", &body)
+}
+
+fn empty_table_box(fourcc: &[u8; 4], is_stsz: bool) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    if is_stsz {
+        body.put_u32(0); // sample_size
+    }
+    body.put_u32(0); // entry_count / sample_count
+    wrap_box(fourcc, &body)
+}
+
+fn opus_stsd_box() -> Bytes {
+    let mut entry = BytesMut::new();
+    entry.put_bytes(0, 6); // reserved
+    entry.put_u16(1); // data_reference_index
+    entry.put_u32(0); // reserved
+    entry.put_u32(0); // reserved
+    entry.put_u16(2); // channelcount
+    entry.put_u16(16); // samplesize
+    entry.put_u16(0); // pre_defined
+    entry.put_u16(0); // reserved
+    entry.put_u32(48_000 << 16); // samplerate, 16.16 fixed point
+    entry.put(dops_box());
+    let entry = wrap_box(b"Opus", &entry);
+
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    body.put_u32(1); // entry_count
+    body.put(entry);
+    wrap_box(b"stsd", &body)
+}
+
+/// `OpusSpecificBox` ("dOps"), per the Opus-in-ISOBMFF mapping.
+fn dops_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u8(0); // version
+    body.put_u8(2); // OutputChannelCount
+    body.put_u16(0); // PreSkip
+    body.put_u32(48_000); // InputSampleRate
+    body.put_i16(0); // OutputGain
+    body.put_u8(0); // ChannelMappingFamily
+    wrap_box(b"dOps", &body)
+}
+
+fn vp09_stsd_box() -> Bytes {
+    let mut entry = BytesMut::new();
+    entry.put_bytes(0, 6); // reserved
+    entry.put_u16(1); // data_reference_index
+    entry.put_u16(0); // pre_defined
+    entry.put_u16(0); // reserved
+    entry.put_u32(0); // pre_defined
+    entry.put_u32(0);
+    entry.put_u32(0);
+    entry.put_u16(VIDEO_WIDTH);
+    entry.put_u16(VIDEO_HEIGHT);
+    entry.put_u32(0x0048_0000); // horizresolution: 72 dpi
+    entry.put_u32(0x0048_0000); // vertresolution: 72 dpi
+    entry.put_u32(0); // reserved
+    entry.put_u16(1); // frame_count
+    entry.put_bytes(0, 32); // compressorname
+    entry.put_u16(0x0018); // depth
+    entry.put_i16(-1); // pre_defined
+    entry.put(vpcc_box());
+    let entry = wrap_box(b"vp09", &entry);
+
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    body.put_u32(1); // entry_count
+    body.put(entry);
+    wrap_box(b"stsd", &body)
+}
+
+/// `VPCodecConfigurationBox` ("vpcC"), per the VP9-in-ISOBMFF mapping.
+/// `codecIntializationDataSize` is 0: VP9 needs no out-of-band codec init
+/// data, unlike AVC's SPS/PPS.
+fn vpcc_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u8(1); // version
+    body.put_u8(0); // flags
+    body.put_u8(0);
+    body.put_u8(0);
+    body.put_u8(0); // profile
+    body.put_u8(10); // level: 1.0
+    body.put_u8(0x82); // bitDepth(8)<<4 | chromaSubsampling(1)<<1 | videoFullRangeFlag(0)
+    body.put_u8(2); // colourPrimaries: unspecified
+    body.put_u8(2); // transferCharacteristics: unspecified
+    body.put_u8(2); // matrixCoefficients: unspecified
+    body.put_u16(0); // codecIntializationDataSize
+    wrap_box(b"vpcC", &body)
+}
+
+fn mvex_box() -> Bytes {
+    let mut body = BytesMut::new();
+    body.put(trex_box(AUDIO_TRACK_ID));
+    body.put(trex_box(VIDEO_TRACK_ID));
+    wrap_box(b"mvex", &body)
+}
+
+fn trex_box(track_id: u32) -> Bytes {
+    let mut body = BytesMut::new();
+    body.put_u32(0); // version/flags
+    body.put_u32(track_id);
+    body.put_u32(1); // default_sample_description_index
+    body.put_u32(0); // default_sample_duration
+    body.put_u32(0); // default_sample_size
+    body.put_u32(0); // default_sample_flags
+    wrap_box(b"trex", &body)
+}
+
+/// Build one `moof`+`mdat` fragment pair for an accumulated batch of
+/// samples. Samples are grouped into at most one `traf` per track (audio,
+/// video) so each track's `trun` carries only its own sample sizes and
+/// durations; `mdat` lays out each track's samples contiguously in the same
+/// order so a single `data_offset` per `traf` locates them.
+fn build_fragment(sequence_number: u32, samples: &[Sample]) -> (Bytes, Bytes) {
+    let audio: Vec<&Sample> = samples.iter().filter(|s| s.media_type == MEDIA_TYPE_AUDIO).collect();
+    let video: Vec<&Sample> = samples.iter().filter(|s| s.media_type == MEDIA_TYPE_VIDEO).collect();
+
+    let mut mdat_payload = BytesMut::new();
+    for sample in video.iter().chain(audio.iter()) {
+        mdat_payload.extend_from_slice(&sample.payload);
+    }
+    let mdat = wrap_box(b"mdat", &mdat_payload);
+
+    let mut mfhd = BytesMut::new();
+    mfhd.put_u32(0); // version/flags
+    mfhd.put_u32(sequence_number);
+    let mfhd = wrap_box(b"mfhd", &mfhd);
+
+    // `trun.data_offset` is relative to the start of this `moof` box (every
+    // `tfhd` sets `default-base-is-moof`, ISO/IEC 14496-12 8.8.7.1, so this
+    // holds for the second `traf` too), so it must be patched once the full
+    // `moof` size is known.
+    let video_traf = video_traf_bytes(&video);
+    let audio_traf = audio_traf_bytes(&audio);
+
+    let mut moof_body = BytesMut::new();
+    moof_body.put(mfhd);
+    let video_traf_len = video_traf.len();
+    moof_body.put_slice(&video_traf);
+    moof_body.put_slice(&audio_traf);
+    let moof_header_len = 8;
+    let moof_len = moof_header_len + moof_body.len();
+
+    let mdat_header_len = 8;
+    if !video.is_empty() {
+        let data_offset = (moof_len + mdat_header_len) as i32;
+        patch_trun_data_offset(&mut moof_body, moof_header_len, data_offset);
+    }
+    if !audio.is_empty() {
+        let video_bytes: usize = video.iter().map(|s| s.payload.len()).sum();
+        let data_offset = (moof_len + mdat_header_len + video_bytes) as i32;
+        let audio_traf_start = moof_header_len + video_traf_len;
+        patch_trun_data_offset(&mut moof_body, audio_traf_start, data_offset);
+    }
+
+    let moof = wrap_box(b"moof", &moof_body);
+    (moof, mdat)
+}
+
+fn video_traf_bytes(video: &[&Sample]) -> BytesMut {
+    if video.is_empty() {
+        return BytesMut::new();
+    }
+    traf_bytes(VIDEO_TRACK_ID, video)
+}
+
+fn audio_traf_bytes(audio: &[&Sample]) -> BytesMut {
+    if audio.is_empty() {
+        return BytesMut::new();
+    }
+    traf_bytes(AUDIO_TRACK_ID, audio)
+}
+
+/// Build a `traf` (track fragment) with a single `trun` carrying, per
+/// sample, the duration (delta from the previous sample's timestamp) and
+/// size — the fields `trun.flags` below actually declares, unlike the
+/// previous version of this writer, which set the sample-size flag but
+/// wrote durations into those slots.
+fn traf_bytes(track_id: u32, samples: &[&Sample]) -> BytesMut {
+    let mut tfhd = BytesMut::new();
+    tfhd.put_u32(0x0002_0000); // version 0, flags: default-base-is-moof
+    tfhd.put_u32(track_id);
+    let tfhd = wrap_box(b"tfhd", &tfhd);
+
+    let mut trun = BytesMut::new();
+    trun.put_u32(0x0000_0301); // flags: data-offset-present | sample-duration-present | sample-size-present
+    trun.put_u32(samples.len() as u32);
+    trun.put_i32(0); // data_offset, patched by build_fragment once moof size is known
+    let mut prev_ts = samples[0].timestamp_ms;
+    for sample in samples {
+        let duration = sample.timestamp_ms.saturating_sub(prev_ts).max(1);
+        prev_ts = sample.timestamp_ms;
+        trun.put_u32(duration);
+        trun.put_u32(sample.payload.len() as u32);
+    }
+    let trun = wrap_box(b"trun", &trun);
+
+    let mut traf = BytesMut::new();
+    traf.put(tfhd);
+    traf.put(trun);
+    wrap_box(b"traf", &traf)
+}
+
+/// Overwrite the `data_offset` field of the first (only) `trun` inside the
+/// `traf` starting at `traf_offset` bytes into `moof_body`.
+fn patch_trun_data_offset(moof_body: &mut BytesMut, traf_offset: usize, data_offset: i32) {
+    // traf header(8) + tfhd box(8 header + 8 body = 16) + trun header(8) +
+    // trun flags(4) + sample_count(4) = offset of the data_offset field.
+    let offset = traf_offset + 8 + 16 + 8 + 4 + 4;
+    moof_body[offset..offset + 4].copy_from_slice(&data_offset.to_be_bytes());
+}
+
+fn wrap_box(fourcc: &[u8; 4], body: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(8 + body.len());
+    buf.put_u32((8 + body.len()) as u32);
+    buf.put_slice(fourcc);
+    buf.put_slice(body);
+    buf.freeze()
+}
@@ -0,0 +1,351 @@
+//! RTMP ingest: lets OBS/ffmpeg publish into a Vox room without a native
+//! client, by terminating an RTMP session and injecting the decoded media
+//! into the same forwarding path QUIC publishers use.
+
+use bytes::Bytes;
+use rml_rtmp::handshake::{Handshake, HandshakeProcessResult, PeerType};
+use rml_rtmp::sessions::{
+    ServerSession, ServerSessionConfig, ServerSessionEvent, ServerSessionResult,
+};
+use rml_rtmp::time::RtmpTimestamp;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::endpoint::forward_to_room;
+use crate::header::{FLAG_SINGLE_LAYER, HEADER_SIZE, MEDIA_TYPE_AUDIO, MEDIA_TYPE_VIDEO};
+use crate::state::SharedState;
+use crate::transcode::{AudioTranscoder, VideoTranscoder};
+
+/// One unit of media demuxed out of the incoming FLV stream, mirroring the
+/// `RtmpInput::Media` / `MediaType::{Video,Audio}` split used by gst-rtmpsrv.
+enum MediaType {
+    Audio,
+    Video,
+}
+
+struct Media {
+    kind: MediaType,
+    data: Bytes,
+    timestamp: RtmpTimestamp,
+    is_keyframe: bool,
+    sequence: u32,
+}
+
+/// Per-publisher transcoding state and the monotonic sequence counters
+/// `forward_media` stamps into the header — one pair per track, mirroring
+/// how vox-media's `ActiveSession` keeps a separate `sequence` for its
+/// audio and video tracks (see `state.rs`).
+struct Transcoders {
+    audio: AudioTranscoder,
+    video: VideoTranscoder,
+    audio_sequence: u32,
+    video_sequence: u32,
+}
+
+impl Transcoders {
+    fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Transcoders {
+            audio: AudioTranscoder::new()?,
+            video: VideoTranscoder::new()?,
+            audio_sequence: 0,
+            video_sequence: 0,
+        })
+    }
+}
+
+/// Run the RTMP ingest listener until cancelled.
+pub async fn run(bind_addr: String, state: SharedState, cancel: CancellationToken) {
+    let addr: std::net::SocketAddr = match bind_addr.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("invalid RTMP bind address {}: {}", bind_addr, e);
+            return;
+        }
+    };
+
+    let listener = match TcpListener::bind(addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::error!("failed to bind RTMP listener on {}: {}", addr, e);
+            return;
+        }
+    };
+
+    tracing::info!("RTMP ingest listening on {}", addr);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                match accepted {
+                    Ok((socket, peer)) => {
+                        let state = state.clone();
+                        let cancel = cancel.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_publisher(socket, state, cancel).await {
+                                tracing::warn!("RTMP session from {} ended: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!("failed to accept RTMP connection: {}", e),
+                }
+            }
+            _ = cancel.cancelled() => {
+                tracing::info!("RTMP ingest shutting down");
+                break;
+            }
+        }
+    }
+}
+
+/// Perform the RTMP handshake, negotiate a publish session, and forward
+/// every decoded media unit into the target room until the stream ends.
+async fn handle_publisher(
+    mut socket: TcpStream,
+    state: SharedState,
+    cancel: CancellationToken,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    perform_handshake(&mut socket).await?;
+
+    let config = ServerSessionConfig::new();
+    let (mut session, initial_results) = ServerSession::new(config)?;
+    let mut outbound = drain_server_results(initial_results);
+
+    // (room_id, user_id) once the stream key has been authenticated against
+    // the media token index; publishing is rejected until then.
+    let mut publisher: Option<(u32, u32)> = None;
+    let mut transcoders = Transcoders::new()?;
+    let mut read_buf = [0u8; 8192];
+
+    loop {
+        if !outbound.is_empty() {
+            socket.write_all(&outbound).await?;
+            outbound.clear();
+        }
+
+        let n = tokio::select! {
+            n = socket.read(&mut read_buf) => n?,
+            _ = cancel.cancelled() => return Ok(()),
+        };
+        if n == 0 {
+            return Ok(());
+        }
+
+        for result in session.handle_input(&read_buf[..n])? {
+            match result {
+                ServerSessionResult::OutboundResponse(packet) => {
+                    outbound.extend_from_slice(&packet.bytes);
+                }
+                ServerSessionResult::RaisedEvent(event) => {
+                    handle_session_event(
+                        event,
+                        &mut session,
+                        &mut publisher,
+                        &state,
+                        &mut outbound,
+                        &mut transcoders,
+                    )
+                    .await?;
+                }
+                ServerSessionResult::UnhandleableMessageReceived(_) => {}
+            }
+        }
+    }
+}
+
+/// Drive `rml_rtmp`'s handshake state machine to completion over the socket.
+async fn perform_handshake(
+    socket: &mut TcpStream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut handshake = Handshake::new(PeerType::Server);
+    let mut buf = [0u8; 4096];
+
+    // The server-side handshake starts by reading the client's C0+C1.
+    let n = socket.read(&mut buf).await?;
+    let mut response = handshake.process_bytes(&buf[..n])?;
+
+    loop {
+        match response {
+            HandshakeProcessResult::InProgress { response_bytes } => {
+                if !response_bytes.is_empty() {
+                    socket.write_all(&response_bytes).await?;
+                }
+                let n = socket.read(&mut buf).await?;
+                if n == 0 {
+                    return Err("RTMP peer closed during handshake".into());
+                }
+                response = handshake.process_bytes(&buf[..n])?;
+            }
+            HandshakeProcessResult::Completed { response_bytes, remaining_bytes } => {
+                if !response_bytes.is_empty() {
+                    socket.write_all(&response_bytes).await?;
+                }
+                if !remaining_bytes.is_empty() {
+                    // Feed leftover post-handshake bytes back through the
+                    // session on the next read; rml_rtmp guarantees they're
+                    // a whole number of RTMP chunks is not guaranteed, so
+                    // the caller's session.handle_input handles partials.
+                    tracing::trace!("{} leftover bytes after RTMP handshake", remaining_bytes.len());
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn handle_session_event(
+    event: ServerSessionEvent,
+    session: &mut ServerSession,
+    publisher: &mut Option<(u32, u32)>,
+    state: &SharedState,
+    outbound: &mut Vec<u8>,
+    transcoders: &mut Transcoders,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match event {
+        ServerSessionEvent::ConnectionRequested { request_id, .. } => {
+            let results = session.accept_request(request_id)?;
+            outbound.extend(drain_server_results(results));
+        }
+        ServerSessionEvent::PublishStreamRequested {
+            request_id,
+            stream_key,
+            ..
+        } => {
+            // Same token_index the QUIC handshake authenticates against (see
+            // `endpoint::handle_connection`), so a stream key is subject to
+            // the same expiry window before its first use.
+            let ids = {
+                let mut st = state.write().await;
+                match st.token_index.get_mut(&stream_key) {
+                    Some(entry) => {
+                        if entry.first_used_at.is_none()
+                            && entry.issued_at.elapsed() > crate::state::TOKEN_EXPIRY
+                        {
+                            None
+                        } else {
+                            entry.first_used_at.get_or_insert_with(std::time::Instant::now);
+                            Some(entry.ids())
+                        }
+                    }
+                    None => None,
+                }
+            };
+            match ids {
+                Some((room_id, user_id)) => {
+                    tracing::info!(
+                        "RTMP publisher authenticated as room={} user={} (stream key)",
+                        room_id, user_id
+                    );
+                    *publisher = Some((room_id, user_id));
+                    let results = session.accept_request(request_id)?;
+                    outbound.extend(drain_server_results(results));
+                }
+                None => {
+                    tracing::warn!("RTMP publish rejected: unknown or expired stream key");
+                    crate::metrics::auth_failure("unknown_token");
+                    return Err("unknown stream key".into());
+                }
+            }
+        }
+        ServerSessionEvent::StreamMetadataChanged { .. } => {}
+        ServerSessionEvent::AudioDataReceived { data, timestamp, .. } => {
+            if let Some((room_id, user_id)) = *publisher {
+                for opus_frame in transcoders.audio.push(&data)? {
+                    let sequence = transcoders.audio_sequence;
+                    transcoders.audio_sequence = transcoders.audio_sequence.wrapping_add(1);
+                    forward_media(
+                        Media {
+                            kind: MediaType::Audio,
+                            data: opus_frame,
+                            timestamp,
+                            is_keyframe: false,
+                            sequence,
+                        },
+                        room_id,
+                        user_id,
+                        state,
+                    )
+                    .await;
+                }
+            }
+        }
+        ServerSessionEvent::VideoDataReceived { data, timestamp, .. } => {
+            if let Some((room_id, user_id)) = *publisher {
+                let force_keyframe = is_flv_video_keyframe(&data);
+                if let Some((vp9_chunk, is_keyframe)) = transcoders.video.push(&data, force_keyframe)? {
+                    let sequence = transcoders.video_sequence;
+                    transcoders.video_sequence = transcoders.video_sequence.wrapping_add(1);
+                    forward_media(
+                        Media {
+                            kind: MediaType::Video,
+                            data: vp9_chunk,
+                            timestamp,
+                            is_keyframe,
+                            sequence,
+                        },
+                        room_id,
+                        user_id,
+                        state,
+                    )
+                    .await;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The first byte of an FLV VIDEODATA tag packs the frame type in the high
+/// nibble; `1` marks a keyframe.
+fn is_flv_video_keyframe(data: &[u8]) -> bool {
+    data.first().map(|b| b >> 4 == 1).unwrap_or(false)
+}
+
+/// Stamp an already-transcoded media unit (Opus audio / VP9 video, produced
+/// by `Transcoders` above) with a `MediaHeader` and inject it into the
+/// room's forwarding path exactly as if it had arrived over QUIC.
+async fn forward_media(media: Media, room_id: u32, user_id: u32, state: &SharedState) {
+    let (media_type, codec_id, flags) = match media.kind {
+        MediaType::Audio => (MEDIA_TYPE_AUDIO, 1u8 /* CODEC_OPUS */, 0u8),
+        MediaType::Video => {
+            // Ingest only ever transcodes to a single VP9 stream (no
+            // simulcast), so subscribers degraded below the base layer must
+            // still receive it rather than have it dropped at the
+            // spatial_id equality gate in `forward_to_room`.
+            let mut flags = FLAG_SINGLE_LAYER;
+            if media.is_keyframe {
+                flags |= 0b1000_0000u8 /* FLAG_KEYFRAME */;
+            }
+            (MEDIA_TYPE_VIDEO, 2u8 /* CODEC_VP9 */, flags)
+        }
+    };
+
+    let mut buf = Vec::with_capacity(HEADER_SIZE + media.data.len());
+    buf.push(1); // version
+    buf.push(media_type);
+    buf.push(codec_id);
+    buf.push(flags);
+    buf.extend_from_slice(&room_id.to_be_bytes());
+    buf.extend_from_slice(&user_id.to_be_bytes());
+    buf.extend_from_slice(&media.sequence.to_be_bytes());
+    // RTMP timestamps are milliseconds; `MediaHeader.timestamp` runs on a
+    // 48kHz clock for audio and a 90kHz clock for video.
+    let ticks_per_ms: u32 = if media_type == MEDIA_TYPE_VIDEO { 90 } else { 48 };
+    buf.extend_from_slice(&media.timestamp.value.wrapping_mul(ticks_per_ms).to_be_bytes());
+    buf.push(0); // spatial_id=0, temporal_id=0 — ingest publishes the base layer only
+    buf.push(0); // dtx=false
+    buf.extend_from_slice(&media.data);
+
+    forward_to_room(&buf, room_id, user_id, state).await;
+}
+
+fn drain_server_results(results: Vec<ServerSessionResult>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for result in results {
+        if let ServerSessionResult::OutboundResponse(packet) = result {
+            out.extend_from_slice(&packet.bytes);
+        }
+    }
+    out
+}
@@ -1,23 +1,102 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicU8};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+/// Number of simulcast video layers the SFU chooses between, highest quality
+/// (0) to lowest (`NUM_VIDEO_LAYERS - 1`), matching `video::SIMULCAST_LAYERS`
+/// on the publishing client.
+pub const NUM_VIDEO_LAYERS: u8 = 3;
+
+/// How long a freshly admitted token may sit unclaimed before the QUIC
+/// handshake rejects it as expired. Only applies before the token's first
+/// successful use — once a peer has authenticated with it, later
+/// reconnects (e.g. after a network blip) are never subject to this
+/// window, since the token is already backing a live user rather than
+/// sitting around as a bearer credential someone could find and replay.
+pub const TOKEN_EXPIRY: Duration = Duration::from_secs(60);
+
 pub type SharedState = Arc<RwLock<State>>;
 
 pub struct State {
     pub rooms: HashMap<u32, Room>,
-    pub token_index: HashMap<String, (u32, u32)>,
+    pub token_index: HashMap<String, TokenEntry>,
+}
+
+/// One admitted media token: which room/user it authenticates, and the
+/// bookkeeping needed for expiry and single-use enforcement at the QUIC
+/// handshake.
+pub struct TokenEntry {
+    pub room_id: u32,
+    pub user_id: u32,
+    pub issued_at: Instant,
+    /// Set the first time this token successfully authenticates a
+    /// connection. `None` means it's still an unclaimed bearer credential
+    /// and subject to `TOKEN_EXPIRY`; once `Some`, the token is tied to a
+    /// live user and reconnects are allowed indefinitely.
+    pub first_used_at: Option<Instant>,
+}
+
+impl TokenEntry {
+    pub fn ids(&self) -> (u32, u32) {
+        (self.room_id, self.user_id)
+    }
 }
 
 pub struct Room {
     pub room_id: u32,
     pub users: HashMap<u32, UserSession>,
+    /// Set while recording is active for this room; `forward_to_room` taps
+    /// every forwarded frame into this channel for the recorder to demux.
+    pub recording_tx: Option<tokio::sync::mpsc::UnboundedSender<RecordedUnit>>,
+}
+
+/// One forwarded media unit, handed to the recording subsystem alongside
+/// the live forwarding path.
+#[derive(Clone)]
+pub struct RecordedUnit {
+    pub user_id: u32,
+    pub media_type: u8,
+    pub is_keyframe: bool,
+    pub timestamp: u32,
+    pub payload: bytes::Bytes,
 }
 
 pub struct UserSession {
     pub user_id: u32,
     pub token: String,
     pub connection: Option<quinn::Connection>,
+    /// The video simulcast layer currently forwarded to this peer (0 =
+    /// highest quality), kept up to date by `run_feedback_stream` from the
+    /// target bitrate this peer reports. Lives outside the `RwLock<State>`
+    /// write path so that task can update it while `forward_to_room` only
+    /// holds a read lock.
+    pub selected_video_layer: Arc<AtomicU8>,
+    /// Most recent target bitrate (bits/sec) this peer's delay-based
+    /// bandwidth estimator reported over its feedback stream. Drives
+    /// `selected_video_layer`; starts at `u32::MAX` (unconstrained) so a
+    /// peer gets the highest layer until its first real estimate arrives.
+    pub target_bitrate_bps: Arc<AtomicU32>,
+    /// For AV1 SVC publishers (frames carrying a Dependency Descriptor), the
+    /// spatial layer currently safe to forward to this peer, keyed by
+    /// publisher user id so multiple simultaneous SVC senders in the same
+    /// room don't share one ceiling. Lags the bandwidth-driven target
+    /// between decode-target switch points: an upgrade to more enhancement
+    /// layers only takes effect once a frame marks itself a switch point,
+    /// while dropping down to fewer layers applies immediately. Unused for
+    /// plain simulcast (non-SVC) publishers.
+    pub svc_layer_ceiling: Arc<std::sync::Mutex<HashMap<u32, u8>>>,
+    /// Audio codec ids this peer can decode, as declared in its auth frame.
+    /// Empty until the peer's QUIC connection authenticates; `forward_to_room`
+    /// treats empty as "not yet known" and does not filter audio by codec
+    /// (the peer has no live connection to forward to at that point anyway).
+    pub supported_codecs: Vec<u8>,
+    /// This peer's outgoing keyframe-object stream currently in flight, per
+    /// (sender_id, layer), so `forward_keyframe_object` can abandon a stale
+    /// one via `reset_stream` as soon as a newer keyframe for the same layer
+    /// shows up rather than finish sending data nobody needs anymore.
+    pub keyframe_streams: Arc<tokio::sync::Mutex<HashMap<(u32, u8), Arc<tokio::sync::Mutex<quinn::SendStream>>>>>,
 }
 
 impl State {
@@ -32,7 +111,9 @@ impl State {
         self.rooms.entry(room_id).or_insert_with(|| Room {
             room_id,
             users: HashMap::new(),
+            recording_tx: None,
         });
+        crate::metrics::set_room_count(self.rooms.len() as i64);
     }
 
     pub fn remove_room(&mut self, room_id: u32) {
@@ -41,11 +122,19 @@ impl State {
                 self.token_index.remove(&session.token);
             }
         }
+        crate::metrics::set_room_count(self.rooms.len() as i64);
     }
 
     pub fn admit_user(&mut self, room_id: u32, user_id: u32, token: &str) {
-        self.token_index
-            .insert(token.to_string(), (room_id, user_id));
+        self.token_index.insert(
+            token.to_string(),
+            TokenEntry {
+                room_id,
+                user_id,
+                issued_at: Instant::now(),
+                first_used_at: None,
+            },
+        );
         if let Some(room) = self.rooms.get_mut(&room_id) {
             room.users.insert(
                 user_id,
@@ -53,6 +142,13 @@ impl State {
                     user_id,
                     token: token.to_string(),
                     connection: None,
+                    // Start at the highest layer; `run_feedback_stream`
+                    // steps it down when the peer reports a lower bitrate.
+                    selected_video_layer: Arc::new(AtomicU8::new(0)),
+                    target_bitrate_bps: Arc::new(AtomicU32::new(u32::MAX)),
+                    svc_layer_ceiling: Arc::new(std::sync::Mutex::new(HashMap::new())),
+                    supported_codecs: Vec::new(),
+                    keyframe_streams: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
                 },
             );
         }
@@ -0,0 +1,260 @@
+//! Transcodes ingested RTMP/FLV media to the project's wire codecs before
+//! it enters the SFU's forwarding path: FLV's AAC audio to Opus (mirroring
+//! `vox_media::codec::OpusEncoder` on the client side) and FLV's H264 video
+//! to VP9 (mirroring `vox_media::codec::VideoEncoder`). Without this, an
+//! RTMP publisher's frames would carry FLV framing and AAC/H264 payloads
+//! while stamped with Opus/VP9 codec ids, and every receiving client would
+//! fail to decode them.
+//!
+//! One `AudioTranscoder`/`VideoTranscoder` lives for the lifetime of one
+//! RTMP publish session, since both the source decoder and the target
+//! encoder are stateful (the AAC decoder tracks its `AudioSpecificConfig`;
+//! the H264 decoder tracks its SPS/PPS; the VP9 encoder tracks its
+//! reference frames).
+
+use bytes::Bytes;
+
+/// FLV `AACPacketType`/`AVCPacketType` values, shared by both the audio and
+/// video branches of this module.
+const PACKET_TYPE_SEQUENCE_HEADER: u8 = 0;
+const PACKET_TYPE_FRAME_DATA: u8 = 1;
+
+/// Decodes FLV AAC audio and re-encodes it to Opus. AAC's frame size (1024
+/// samples at the source sample rate) never lines up with Opus's fixed
+/// 960-sample (20ms @ 48kHz) frame, so decoded samples are resampled into a
+/// rolling buffer and drained in exact 960-sample slices.
+pub struct AudioTranscoder {
+    decoder: fdk_aac::dec::Decoder,
+    encoder: opus::Encoder,
+    source_rate: Option<u32>,
+    /// Mono 48kHz samples decoded so far but not yet long enough for a full
+    /// Opus frame.
+    pending: Vec<i16>,
+}
+
+impl AudioTranscoder {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(AudioTranscoder {
+            decoder: fdk_aac::dec::Decoder::new(fdk_aac::dec::Transport::Raw),
+            encoder: opus::Encoder::new(48_000, opus::Channels::Mono, opus::Application::Voip)?,
+            source_rate: None,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Feed one FLV AUDIODATA tag body (the `SoundFormat`/`AACPacketType`
+    /// bytes are still attached, as `rml_rtmp` hands them over). Returns
+    /// zero or more complete 20ms Opus frames; an `AACPacketType` of
+    /// `PACKET_TYPE_SEQUENCE_HEADER` configures the raw-transport decoder
+    /// with the `AudioSpecificConfig` it carries (instead of any audio) and
+    /// always yields none — frame-data packets decode to silence-producing
+    /// `NotEnoughBits` errors forever if this step is skipped, since a raw
+    /// fdk_aac decoder has no ADTS header to infer sample rate/channels/AOT
+    /// from.
+    pub fn push(&mut self, flv_audio_data: &[u8]) -> Result<Vec<Bytes>, Box<dyn std::error::Error + Send + Sync>> {
+        let [_sound_header, packet_type, payload @ ..] = flv_audio_data else {
+            return Ok(Vec::new());
+        };
+        if *packet_type == PACKET_TYPE_SEQUENCE_HEADER {
+            self.decoder.config_raw(&[payload])?;
+            return Ok(Vec::new());
+        }
+        if *packet_type != PACKET_TYPE_FRAME_DATA {
+            return Ok(Vec::new());
+        }
+
+        self.decoder.fill(payload)?;
+        let mut out_frames = Vec::new();
+        let mut pcm = [0i16; 8192];
+        loop {
+            match self.decoder.decode_frame(&mut pcm) {
+                Ok(()) => {}
+                Err(fdk_aac::dec::DecoderError::NotEnoughBits) => break,
+                Err(e) => return Err(e.into()),
+            }
+            let info = self.decoder.stream_info();
+            let rate = *self.source_rate.get_or_insert(info.sampleRate as u32);
+            let channels = (info.numChannels.max(1)) as usize;
+            let frame_len = info.frameSize as usize * channels;
+            let mono = downmix_to_mono(&pcm[..frame_len.min(pcm.len())], channels);
+            resample_linear(&mono, rate, 48_000, &mut self.pending);
+
+            while self.pending.len() >= 960 {
+                let frame: Vec<i16> = self.pending.drain(..960).collect();
+                let mut encoded = vec![0u8; 4000]; // max Opus frame
+                let len = self.encoder.encode(&frame, &mut encoded)?;
+                encoded.truncate(len);
+                out_frames.push(Bytes::from(encoded));
+            }
+        }
+        Ok(out_frames)
+    }
+}
+
+/// Average interleaved channels down to mono; a no-op copy when already mono.
+fn downmix_to_mono(pcm: &[i16], channels: usize) -> Vec<i16> {
+    if channels <= 1 {
+        return pcm.to_vec();
+    }
+    pcm.chunks(channels)
+        .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / channels as i32) as i16)
+        .collect()
+}
+
+/// Linear-interpolation resample from `from_hz` to `to_hz`, appending onto
+/// `out`. Good enough for a voice-bandwidth AAC source — this pipeline only
+/// ever re-encodes to Opus at Voip quality, so a higher-order (e.g.
+/// polyphase) resampler wouldn't preserve anything worth keeping.
+fn resample_linear(input: &[i16], from_hz: u32, to_hz: u32, out: &mut Vec<i16>) {
+    if input.is_empty() {
+        return;
+    }
+    if from_hz == to_hz {
+        out.extend_from_slice(input);
+        return;
+    }
+    let ratio = from_hz as f64 / to_hz as f64;
+    let out_len = (input.len() as f64 / ratio) as usize;
+    for i in 0..out_len {
+        let src_pos = i as f64 * ratio;
+        let idx = src_pos as usize;
+        let frac = src_pos - idx as f64;
+        let a = input[idx.min(input.len() - 1)] as f64;
+        let b = input[(idx + 1).min(input.len() - 1)] as f64;
+        out.push((a + (b - a) * frac).round() as i16);
+    }
+}
+
+/// Decodes FLV H264 and re-encodes it to VP9. Carries the AVC NALU length
+/// size (parsed out of the `AVCDecoderConfigurationRecord` sequence header)
+/// and the VP9 encoder, which is (re)created once the decoder reports the
+/// source frame's dimensions.
+pub struct VideoTranscoder {
+    decoder: openh264::decoder::Decoder,
+    encoder: Option<vpx_encode::Encoder>,
+    nalu_length_size: usize,
+    next_pts: i64,
+}
+
+impl VideoTranscoder {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(VideoTranscoder {
+            decoder: openh264::decoder::Decoder::new()?,
+            encoder: None,
+            nalu_length_size: 4,
+            next_pts: 0,
+        })
+    }
+
+    /// Feed one FLV VIDEODATA tag body (`FrameType`/`CodecID`,
+    /// `AVCPacketType`, and the 3-byte `CompositionTime` are still
+    /// attached). Returns the re-encoded VP9 chunk plus whether it's a
+    /// keyframe, or `None` for an AVC sequence header or a frame the H264
+    /// decoder can't yet emit a picture from (e.g. before its first SPS/PPS).
+    /// `force_keyframe` carries the source FLV frame's own keyframe flag
+    /// through to the VP9 encoder, so a late subscriber still has the same
+    /// entry points into the stream the source encoder intended.
+    pub fn push(
+        &mut self,
+        flv_video_data: &[u8],
+        force_keyframe: bool,
+    ) -> Result<Option<(Bytes, bool)>, Box<dyn std::error::Error + Send + Sync>> {
+        let [_frame_codec, packet_type, _ct0, _ct1, _ct2, payload @ ..] = flv_video_data else {
+            return Ok(None);
+        };
+        match *packet_type {
+            PACKET_TYPE_SEQUENCE_HEADER => {
+                // AVCDecoderConfigurationRecord byte 4's low 2 bits carry
+                // lengthSizeMinusOne.
+                if let Some(&b) = payload.get(4) {
+                    self.nalu_length_size = (b & 0x03) as usize + 1;
+                }
+                Ok(None)
+            }
+            PACKET_TYPE_FRAME_DATA => {
+                let annex_b = avcc_to_annexb(payload, self.nalu_length_size);
+                let Some(yuv) = self.decoder.decode(&annex_b)? else {
+                    return Ok(None);
+                };
+                let (width, height) = yuv.dimensions();
+                let i420 = yuv_to_i420(&yuv);
+
+                let encoder = match &mut self.encoder {
+                    Some(e) if e.width() == width as u32 && e.height() == height as u32 => e,
+                    _ => {
+                        let config = vpx_encode::Config {
+                            width: width as u32,
+                            height: height as u32,
+                            timebase: [1, 90_000],
+                            bitrate: 2_000,
+                            codec: vpx_encode::VideoCodecId::VP9,
+                        };
+                        self.encoder = Some(vpx_encode::Encoder::new(config)?);
+                        self.encoder.as_mut().expect("just assigned")
+                    }
+                };
+
+                let flags = if force_keyframe {
+                    vpx_encode::EncodeFlags::FORCE_KEYFRAME
+                } else {
+                    vpx_encode::EncodeFlags::empty()
+                };
+                let pts = self.next_pts;
+                self.next_pts += 1;
+                let mut data = Vec::new();
+                let mut is_keyframe = force_keyframe;
+                for packet in encoder.encode(pts, &i420, flags)? {
+                    if let vpx_encode::Packet::Frame { data: chunk, keyframe, .. } = packet {
+                        is_keyframe |= keyframe;
+                        data.extend_from_slice(chunk);
+                    }
+                }
+                if data.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some((Bytes::from(data), is_keyframe)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Copy a decoded frame's Y/U/V planes (each with its own stride) into a
+/// tightly-packed I420 buffer, the layout `vpx_encode::Encoder::encode`
+/// expects.
+fn yuv_to_i420(yuv: &openh264::decoder::DecodedYUV) -> Vec<u8> {
+    let (width, height) = yuv.dimensions();
+    let (y_stride, u_stride, v_stride) = yuv.strides();
+    let mut out = Vec::with_capacity(width * height * 3 / 2);
+    for row in 0..height {
+        out.extend_from_slice(&yuv.y()[row * y_stride..row * y_stride + width]);
+    }
+    for row in 0..height.div_ceil(2) {
+        out.extend_from_slice(&yuv.u()[row * u_stride..row * u_stride + width.div_ceil(2)]);
+    }
+    for row in 0..height.div_ceil(2) {
+        out.extend_from_slice(&yuv.v()[row * v_stride..row * v_stride + width.div_ceil(2)]);
+    }
+    out
+}
+
+/// Rewrite AVCC length-prefixed NALUs (each NALU preceded by a
+/// `nalu_length_size`-byte big-endian length instead of a start code) into
+/// Annex B (each NALU preceded by a `00 00 00 01` start code), which is what
+/// `openh264`'s decoder expects.
+fn avcc_to_annexb(data: &[u8], nalu_length_size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    let mut pos = 0;
+    while pos + nalu_length_size <= data.len() {
+        let mut len = 0usize;
+        for b in &data[pos..pos + nalu_length_size] {
+            len = (len << 8) | *b as usize;
+        }
+        pos += nalu_length_size;
+        let end = (pos + len).min(data.len());
+        out.extend_from_slice(&[0, 0, 0, 1]);
+        out.extend_from_slice(&data[pos..end]);
+        pos = end;
+    }
+    out
+}
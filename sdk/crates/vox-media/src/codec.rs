@@ -1,11 +1,69 @@
-//! Opus codec encode/decode wrappers.
+//! Opus and video codec encode/decode wrappers.
 
 use bytes::Bytes;
+use std::fmt;
+
+/// Error type shared by every `AudioCodec` implementation, so callers don't
+/// need to match on a different error enum per codec.
+#[derive(Debug)]
+pub struct CodecError(String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<opus::Error> for CodecError {
+    fn from(e: opus::Error) -> Self {
+        CodecError(e.to_string())
+    }
+}
+
+/// Common interface for negotiated audio codecs. `MediaHeader.codec_id`
+/// already carries which one produced a given frame; this trait lets
+/// `ActiveSession` hold whichever codec was negotiated at connect time
+/// instead of hardwiring Opus.
+pub trait AudioCodec: Send {
+    /// The `codec_id` this implementation stamps into outgoing headers.
+    fn codec_id(&self) -> u8;
+    fn encode(&mut self, pcm: &[i16]) -> Result<Bytes, CodecError>;
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<i16>, CodecError>;
+
+    /// Reconstruct a missing frame from in-band FEC carried in the next
+    /// packet. Codecs without FEC support can leave this as an error.
+    fn decode_fec(&mut self, _next_packet: &[u8]) -> Result<Vec<i16>, CodecError> {
+        Err(CodecError(format!("codec {} does not support FEC", self.codec_id())))
+    }
+
+    /// Synthesize a packet-loss-concealment frame for a fully lost packet.
+    fn decode_plc(&mut self) -> Result<Vec<i16>, CodecError> {
+        Err(CodecError(format!("codec {} does not support PLC", self.codec_id())))
+    }
+
+    /// Whether this codec's encoder currently embeds in-band FEC redundancy
+    /// in outgoing packets, so `OutFrame` can stamp `FLAG_FEC` on them.
+    /// Codecs without FEC support always return false.
+    fn fec_enabled(&self) -> bool {
+        false
+    }
+
+    /// Whether an already-encoded packet is a DTX comfort-noise update
+    /// rather than a full voice frame, so the caller can stamp `dtx` on the
+    /// outgoing header. Codecs without DTX support always return false.
+    fn is_dtx_frame(&self, _encoded: &[u8]) -> bool {
+        false
+    }
+}
 
 /// Opus encoder wrapper.
 pub struct OpusEncoder {
     inner: opus::Encoder,
     frame_size: usize,
+    fec_enabled: bool,
+    dtx_enabled: bool,
 }
 
 impl OpusEncoder {
@@ -15,6 +73,8 @@ impl OpusEncoder {
         Ok(OpusEncoder {
             inner: encoder,
             frame_size: 960, // 20ms at 48kHz
+            fec_enabled: false,
+            dtx_enabled: false,
         })
     }
 
@@ -29,6 +89,43 @@ impl OpusEncoder {
     pub fn frame_size(&self) -> usize {
         self.frame_size
     }
+
+    /// Enable or disable in-band FEC: when on, each packet embeds a
+    /// low-bitrate redundant copy of the *previous* frame, which
+    /// `OpusDecoder::decode_fec` can pull out to reconstruct a single lost
+    /// packet without a retransmit round-trip.
+    pub fn set_inband_fec(&mut self, enabled: bool) -> Result<(), opus::Error> {
+        self.inner.set_inband_fec(enabled)?;
+        self.fec_enabled = enabled;
+        Ok(())
+    }
+
+    /// Tell libopus how lossy the link is expected to be (0-100). Higher
+    /// values make it spend more bits on FEC redundancy per `set_inband_fec`.
+    pub fn set_packet_loss_perc(&mut self, pct: u8) -> Result<(), opus::Error> {
+        self.inner.set_packet_loss_perc(pct as i32)
+    }
+
+    /// Whether in-band FEC is currently enabled.
+    pub fn fec_enabled(&self) -> bool {
+        self.fec_enabled
+    }
+
+    /// Enable or disable discontinuous transmission: when on, libopus stops
+    /// returning full frames during silence, instead periodically emitting a
+    /// 1-2 byte comfort-noise update (and nothing at all between updates),
+    /// which cuts uplink bandwidth in rooms where most participants aren't
+    /// speaking.
+    pub fn set_dtx(&mut self, enabled: bool) -> Result<(), opus::Error> {
+        self.inner.set_dtx(enabled)?;
+        self.dtx_enabled = enabled;
+        Ok(())
+    }
+
+    /// Whether DTX is currently enabled.
+    pub fn dtx_enabled(&self) -> bool {
+        self.dtx_enabled
+    }
 }
 
 /// Opus decoder wrapper.
@@ -55,7 +152,240 @@ impl OpusDecoder {
         Ok(output)
     }
 
+    /// Reconstruct a missing frame from the in-band FEC redundancy carried
+    /// in the *following* packet. Only works if the encoder had FEC enabled.
+    pub fn decode_fec(&mut self, next_packet: &[u8]) -> Result<Vec<i16>, opus::Error> {
+        let mut output = vec![0i16; self.frame_size];
+        let len = self.inner.decode(next_packet, &mut output, true)?;
+        output.truncate(len);
+        Ok(output)
+    }
+
+    /// Synthesize a packet-loss-concealment frame when no later packet is
+    /// available to recover the gap from.
+    pub fn decode_plc(&mut self) -> Result<Vec<i16>, opus::Error> {
+        let mut output = vec![0i16; self.frame_size];
+        let len = self.inner.decode(&[], &mut output, false)?;
+        output.truncate(len);
+        Ok(output)
+    }
+
     pub fn frame_size(&self) -> usize {
         self.frame_size
     }
 }
+
+/// Expected packet loss percentage reported to libopus when FEC is enabled.
+/// The SFU fans every packet out over its own lossy QUIC datagram path, not
+/// just the publisher's, so a modest default is worth the extra bits even
+/// without a live loss estimate to tune it from.
+const DEFAULT_EXPECTED_LOSS_PCT: u8 = 10;
+
+/// A DTX comfort-noise update is libopus's smallest possible non-empty
+/// packet (a SID frame carries nothing but an updated noise level); a real
+/// voice frame at any usable bitrate is always larger than this.
+const MAX_DTX_FRAME_BYTES: usize = 2;
+
+/// Opus implementation of `AudioCodec`, wrapping a paired encoder/decoder —
+/// the only codec this build negotiates today, but callers should go
+/// through the trait so a future low-complexity fallback slots in cleanly.
+pub struct OpusCodec {
+    encoder: OpusEncoder,
+    decoder: OpusDecoder,
+}
+
+impl OpusCodec {
+    pub fn new(dtx_enabled: bool) -> Result<Self, opus::Error> {
+        let mut encoder = OpusEncoder::new()?;
+        encoder.set_inband_fec(true)?;
+        encoder.set_packet_loss_perc(DEFAULT_EXPECTED_LOSS_PCT)?;
+        encoder.set_dtx(dtx_enabled)?;
+        Ok(OpusCodec {
+            encoder,
+            decoder: OpusDecoder::new()?,
+        })
+    }
+}
+
+impl AudioCodec for OpusCodec {
+    fn codec_id(&self) -> u8 {
+        crate::quic::CODEC_OPUS
+    }
+
+    fn encode(&mut self, pcm: &[i16]) -> Result<Bytes, CodecError> {
+        Ok(self.encoder.encode(pcm)?)
+    }
+
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<i16>, CodecError> {
+        Ok(self.decoder.decode(data)?)
+    }
+
+    fn decode_fec(&mut self, next_packet: &[u8]) -> Result<Vec<i16>, CodecError> {
+        Ok(self.decoder.decode_fec(next_packet)?)
+    }
+
+    fn decode_plc(&mut self) -> Result<Vec<i16>, CodecError> {
+        Ok(self.decoder.decode_plc()?)
+    }
+
+    fn fec_enabled(&self) -> bool {
+        self.encoder.fec_enabled()
+    }
+
+    fn is_dtx_frame(&self, encoded: &[u8]) -> bool {
+        self.encoder.dtx_enabled() && !encoded.is_empty() && encoded.len() <= MAX_DTX_FRAME_BYTES
+    }
+}
+
+/// VP9 video encoder wrapper, one instance per simulcast layer.
+///
+/// Mirrors `OpusEncoder`: construct once per layer, call `encode` per source
+/// frame to get back a complete encoded chunk for that layer.
+pub struct VideoEncoder {
+    inner: vpx_encode::Encoder,
+    width: u32,
+    height: u32,
+}
+
+impl VideoEncoder {
+    /// Create an encoder for one simulcast layer at the given resolution and
+    /// target bitrate.
+    pub fn new(width: u32, height: u32, target_bitrate_kbps: u32) -> Result<Self, vpx_encode::Error> {
+        let config = vpx_encode::Config {
+            width,
+            height,
+            timebase: [1, 90_000],
+            bitrate: target_bitrate_kbps,
+            codec: vpx_encode::VideoCodecId::VP9,
+        };
+        Ok(VideoEncoder {
+            inner: vpx_encode::Encoder::new(config)?,
+            width,
+            height,
+        })
+    }
+
+    /// Encode one I420 frame. `force_keyframe` should be set periodically
+    /// (e.g. every 2s) and on simulcast-layer (re)start so a late subscriber
+    /// always has a decodable entry point.
+    pub fn encode(&mut self, i420: &[u8], pts: i64, force_keyframe: bool) -> Result<EncodedVideoFrame, vpx_encode::Error> {
+        let flags = if force_keyframe {
+            vpx_encode::EncodeFlags::FORCE_KEYFRAME
+        } else {
+            vpx_encode::EncodeFlags::empty()
+        };
+        let mut is_keyframe = force_keyframe;
+        let mut data = Vec::new();
+        for packet in self.inner.encode(pts, i420, flags)? {
+            if let vpx_encode::Packet::Frame { data: chunk, keyframe, .. } = packet {
+                is_keyframe |= keyframe;
+                data.extend_from_slice(chunk);
+            }
+        }
+        Ok(EncodedVideoFrame {
+            data: Bytes::from(data),
+            is_keyframe,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// A single encoded video chunk for one simulcast layer of one source frame.
+pub struct EncodedVideoFrame {
+    pub data: Bytes,
+    pub is_keyframe: bool,
+}
+
+/// VP9 video decoder wrapper.
+pub struct VideoDecoder {
+    inner: vpx_encode::Decoder,
+}
+
+impl VideoDecoder {
+    pub fn new() -> Result<Self, vpx_encode::Error> {
+        Ok(VideoDecoder {
+            inner: vpx_encode::Decoder::new(vpx_encode::VideoCodecId::VP9)?,
+        })
+    }
+
+    /// Decode one complete (reassembled) encoded chunk into an I420 frame.
+    /// Returns `Ok(None)` if the decoder needs more data before it can emit
+    /// a frame (e.g. it is still waiting on a keyframe).
+    pub fn decode(&mut self, data: &[u8]) -> Result<Option<crate::video::VideoFrame>, vpx_encode::Error> {
+        self.inner.decode(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 20ms of silence at 48kHz mono — enough for a real encode/decode
+    /// roundtrip without needing actual voice samples.
+    fn silence_frame() -> Vec<i16> {
+        vec![0i16; 960]
+    }
+
+    #[test]
+    fn opus_codec_enables_inband_fec_by_default() {
+        let codec = OpusCodec::new(false).expect("opus init");
+        assert!(codec.fec_enabled());
+    }
+
+    #[test]
+    fn opus_encoder_fec_enabled_tracks_set_inband_fec() {
+        let mut encoder = OpusEncoder::new().expect("opus init");
+        assert!(!encoder.fec_enabled());
+        encoder.set_inband_fec(true).expect("set fec");
+        assert!(encoder.fec_enabled());
+        encoder.set_inband_fec(false).expect("set fec");
+        assert!(!encoder.fec_enabled());
+    }
+
+    #[test]
+    fn decode_fec_recovers_a_frame_from_the_following_packet() {
+        let mut codec = OpusCodec::new(false).expect("opus init");
+        let _first = codec.encode(&silence_frame()).expect("encode first");
+        let second = codec.encode(&silence_frame()).expect("encode second");
+
+        // `_first` never reaches the decoder at all (simulating a lost
+        // packet); `second`'s in-band FEC redundancy reconstructs it instead.
+        let mut decoder = OpusDecoder::new().expect("opus init");
+        let fec_pcm = decoder.decode_fec(&second).expect("decode_fec");
+        assert_eq!(fec_pcm.len(), decoder.frame_size());
+    }
+
+    #[test]
+    fn opus_encoder_dtx_enabled_tracks_set_dtx() {
+        let mut encoder = OpusEncoder::new().expect("opus init");
+        assert!(!encoder.dtx_enabled());
+        encoder.set_dtx(true).expect("set dtx");
+        assert!(encoder.dtx_enabled());
+        encoder.set_dtx(false).expect("set dtx");
+        assert!(!encoder.dtx_enabled());
+    }
+
+    #[test]
+    fn is_dtx_frame_requires_dtx_to_be_enabled() {
+        let codec = OpusCodec::new(false).expect("opus init");
+        // Even a packet within the DTX size bound isn't a DTX frame unless
+        // the encoder actually has DTX turned on.
+        assert!(!codec.is_dtx_frame(&[1]));
+    }
+
+    #[test]
+    fn is_dtx_frame_matches_the_comfort_noise_size_bound() {
+        let codec = OpusCodec::new(true).expect("opus init");
+        assert!(!codec.is_dtx_frame(&[]));
+        assert!(codec.is_dtx_frame(&[1]));
+        assert!(codec.is_dtx_frame(&[1, 2]));
+        assert!(!codec.is_dtx_frame(&[1, 2, 3]));
+    }
+}
@@ -0,0 +1,425 @@
+//! Adaptive jitter buffer for incoming audio: reorders datagrams by
+//! `MediaHeader.sequence` and paces playout at a fixed 20ms cadence instead
+//! of decoding in arrival order, so reordering/duplicates/loss over QUIC
+//! datagrams don't translate directly into audible glitches.
+//!
+//! The target playout depth isn't fixed — it tracks an RFC 3550-style
+//! exponentially-weighted estimate of inter-arrival jitter (computed from
+//! `MediaHeader.timestamp`, a 48kHz clock, against wall-clock arrival time),
+//! clamped between a 20ms floor and a 200ms ceiling. The target decides how
+//! many frames are buffered before playout starts; once playout has started
+//! the cursor advances one frame per tick, and later changes to the target
+//! (including its decay across DTX silence) do not change how much is
+//! buffered.
+
+use crate::quic::MediaHeader;
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Playout tick cadence, matching `ActiveSession::playout_ticker`.
+const FRAME_MS: u32 = 20;
+/// `MediaHeader.timestamp` runs at 48kHz for audio (state.rs's
+/// `send_audio_frame` advances it by 960 per 20ms frame).
+const SAMPLE_RATE: u32 = 48_000;
+
+/// Playout depth floor and ceiling, in milliseconds.
+const MIN_DEPTH_MS: u32 = 20;
+const MAX_DEPTH_MS: u32 = 200;
+/// How many multiples of the smoothed jitter estimate to keep buffered.
+/// RFC 3550 jitter is closer to a mean absolute deviation than a hard bound,
+/// so a handful of multiples absorbs typical bursts without chasing every
+/// single late packet.
+const DEPTH_JITTER_MULTIPLIER: f64 = 4.0;
+/// RFC 3550 jitter smoothing: `J += (|D| - J) / 16`.
+const JITTER_SMOOTHING_DIVISOR: f64 = 16.0;
+/// How hard to decay the playout depth target (not the jitter estimate
+/// itself) on each slot played out as DTX silence. The target is only
+/// consulted while priming, so the decay does not shrink a buffer that is
+/// already playing out.
+const SILENCE_DECAY: f64 = 0.5;
+/// Playout depth to start at, before enough arrivals have come in for the
+/// jitter EWMA to mean anything — matches the fixed depth this buffer used
+/// before it became adaptive, so a call doesn't start with zero cushion.
+const DEFAULT_DEPTH_FRAMES: u32 = 3;
+
+/// What the playout tick should do with the decoder for this 20ms slot.
+pub enum PlayoutAction {
+    /// The expected frame arrived in time — decode it normally.
+    Play(Bytes),
+    /// The expected frame is missing but the *next* one has arrived —
+    /// reconstruct it from Opus in-band FEC carried in `next`.
+    Fec(Bytes),
+    /// The expected frame is missing, but the sender marked the surrounding
+    /// frames DTX (comfort-noise silence) — emit silence instead of
+    /// spending a PLC concealment on what was never lost audio.
+    Silence,
+    /// Nothing usable is available — synthesize a concealment frame.
+    Conceal,
+    /// Still filling the initial playout delay; emit nothing yet.
+    Wait,
+}
+
+/// Snapshot of call-quality counters, reported to Python via
+/// `MediaEvent::CallStats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JitterStats {
+    /// Frames currently sitting in the buffer, ahead of the playout cursor.
+    pub buffered_frames: u32,
+    /// Smoothed inter-arrival jitter estimate, in milliseconds.
+    pub jitter_ms: u32,
+    /// Gaps in the sequence space the playout cursor had to paper over
+    /// (recovered via FEC or concealed), across the life of the buffer.
+    pub lost: u32,
+    /// Packets that arrived after the playout cursor had already passed
+    /// their slot and were dropped as too late to matter.
+    pub late: u32,
+}
+
+struct Entry {
+    payload: Bytes,
+    dtx: bool,
+}
+
+/// Returns true if `a` precedes `b` on the wrapping `u32` sequence space.
+fn precedes(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+pub struct JitterBuffer {
+    buffer: BTreeMap<u32, Entry>,
+    /// Sequence number of the next frame playout is waiting on, once primed.
+    cursor: Option<u32>,
+    /// Current playout depth target, in frames, recomputed as the jitter
+    /// estimate moves; only consulted while priming (`cursor` is `None`).
+    target_depth_frames: u32,
+    /// (arrival time, header timestamp, was-dtx) of the most recently
+    /// inserted packet, used to compute the next inter-arrival delta.
+    last_arrival: Option<(Instant, u32, bool)>,
+    /// EWMA jitter estimate, in samples at `SAMPLE_RATE`.
+    jitter_samples: f64,
+    /// Whether the last slot played out was DTX silence. A sender with DTX
+    /// enabled stops transmitting entirely between comfort-noise updates, so
+    /// a silence span can leave many consecutive sequence numbers with
+    /// nothing buffered at all — once we know we're inside one, an empty gap
+    /// keeps reading as `Silence` rather than loss until a real frame (or a
+    /// fresh comfort-noise update) shows up again.
+    in_dtx_silence: bool,
+    lost: u32,
+    late: u32,
+}
+
+impl JitterBuffer {
+    pub fn new() -> Self {
+        JitterBuffer {
+            buffer: BTreeMap::new(),
+            cursor: None,
+            target_depth_frames: DEFAULT_DEPTH_FRAMES,
+            last_arrival: None,
+            jitter_samples: 0.0,
+            in_dtx_silence: false,
+            lost: 0,
+            late: 0,
+        }
+    }
+
+    /// Insert an arrived payload, reordering by `header.sequence`. Drops it,
+    /// counting it as late, if it's older than the playout cursor (too late
+    /// to matter); a duplicate of a still-buffered sequence replaces the
+    /// earlier copy.
+    pub fn insert(&mut self, header: &MediaHeader, payload: Bytes, now: Instant) {
+        if let Some(cursor) = self.cursor {
+            if precedes(header.sequence, cursor) {
+                self.late += 1;
+                return;
+            }
+        }
+        self.update_jitter(header.timestamp, now, header.dtx);
+        self.buffer.insert(
+            header.sequence,
+            Entry {
+                payload,
+                dtx: header.dtx,
+            },
+        );
+    }
+
+    /// Feed one arrival into the RFC 3550-style jitter EWMA and recompute
+    /// the target playout depth from it. DTX arrivals are tracked (so the
+    /// next real delta isn't measured across a silence gap) but don't
+    /// themselves contribute a sample — comfort-noise cadence isn't
+    /// network jitter, and folding it in would make `jitter_ms` (reported
+    /// via `MediaEvent::CallStats`) read artificially low during pauses.
+    fn update_jitter(&mut self, timestamp: u32, now: Instant, dtx: bool) {
+        if let Some((last_recv, last_ts, last_dtx)) = self.last_arrival {
+            if !dtx && !last_dtx {
+                let recv_delta_samples = now.duration_since(last_recv).as_secs_f64() * SAMPLE_RATE as f64;
+                let ts_delta_samples = timestamp.wrapping_sub(last_ts) as i32 as f64;
+                let d = (recv_delta_samples - ts_delta_samples).abs();
+                self.jitter_samples += (d - self.jitter_samples) / JITTER_SMOOTHING_DIVISOR;
+                self.recompute_target_depth();
+            }
+        }
+        self.last_arrival = Some((now, timestamp, dtx));
+    }
+
+    fn recompute_target_depth(&mut self) {
+        let jitter_ms = self.jitter_samples / (SAMPLE_RATE as f64 / 1000.0);
+        let target_ms = (jitter_ms * DEPTH_JITTER_MULTIPLIER).clamp(MIN_DEPTH_MS as f64, MAX_DEPTH_MS as f64);
+        self.target_depth_frames = ((target_ms / FRAME_MS as f64).round() as u32).max(1);
+    }
+
+    /// Advance playout by one 20ms slot.
+    pub fn tick(&mut self) -> PlayoutAction {
+        let cursor = match self.cursor {
+            Some(c) => c,
+            None => {
+                // Prime the buffer: wait until we have enough lookahead to
+                // absorb the currently-estimated jitter before starting
+                // playout.
+                if (self.buffer.len() as u32) < self.target_depth_frames {
+                    return PlayoutAction::Wait;
+                }
+                let first = *self.buffer.keys().next().unwrap();
+                self.cursor = Some(first);
+                first
+            }
+        };
+
+        let action = if let Some(entry) = self.buffer.remove(&cursor) {
+            if entry.dtx {
+                PlayoutAction::Silence
+            } else {
+                PlayoutAction::Play(entry.payload)
+            }
+        } else if let Some(next) = self.buffer.get(&cursor.wrapping_add(1)) {
+            if next.dtx || self.in_dtx_silence {
+                // Either the sender went silent right where we have a gap,
+                // or we're still inside a silence span it started earlier
+                // (DTX skips transmitting entirely between comfort-noise
+                // updates, so this slot never had a packet coming) — either
+                // way it's DTX, not loss; nothing to conceal.
+                PlayoutAction::Silence
+            } else {
+                self.lost += 1;
+                PlayoutAction::Fec(next.payload.clone())
+            }
+        } else if self.in_dtx_silence {
+            // Nothing buffered at all, but we're still inside a silence
+            // span — DTX sends nothing for many consecutive slots between
+            // updates, so an empty gap here is expected, not lost audio.
+            PlayoutAction::Silence
+        } else {
+            self.lost += 1;
+            PlayoutAction::Conceal
+        };
+
+        self.in_dtx_silence = matches!(action, PlayoutAction::Silence);
+
+        if matches!(action, PlayoutAction::Silence) {
+            // Shrink the playout depth directly during silence, without
+            // touching the jitter estimate itself (that stays a read on
+            // the network, not on whether anyone's currently talking).
+            self.target_depth_frames = ((self.target_depth_frames as f64 * SILENCE_DECAY) as u32)
+                .max(MIN_DEPTH_MS / FRAME_MS);
+        }
+
+        self.cursor = Some(cursor.wrapping_add(1));
+        // Bound memory: drop anything that fell behind the new cursor
+        // (duplicates or very late arrivals we'll never play).
+        let new_cursor = self.cursor.unwrap();
+        self.buffer.retain(|&seq, _| !precedes(seq, new_cursor));
+
+        action
+    }
+
+    /// Snapshot the current call-quality counters for `MediaEvent::CallStats`.
+    pub fn stats(&self) -> JitterStats {
+        JitterStats {
+            buffered_frames: self.buffer.len() as u32,
+            jitter_ms: (self.jitter_samples / (SAMPLE_RATE as f64 / 1000.0)) as u32,
+            lost: self.lost,
+            late: self.late,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn hdr(sequence: u32, timestamp: u32, dtx: bool) -> MediaHeader {
+        MediaHeader {
+            version: 1,
+            media_type: 0,
+            codec_id: 1,
+            flags: 0,
+            room_id: 1,
+            user_id: 1,
+            sequence,
+            timestamp,
+            spatial_id: 0,
+            temporal_id: 0,
+            dtx,
+        }
+    }
+
+    #[test]
+    fn precedes_handles_u32_wraparound() {
+        assert!(precedes(u32::MAX, 0));
+        assert!(!precedes(0, u32::MAX));
+        assert!(precedes(5, 10));
+        assert!(!precedes(10, 5));
+        assert!(!precedes(5, 5));
+    }
+
+    #[test]
+    fn tick_waits_until_the_target_depth_is_buffered() {
+        let mut jb = JitterBuffer::new();
+        let now = Instant::now();
+        jb.insert(&hdr(0, 0, false), Bytes::from_static(b"a"), now);
+        jb.insert(&hdr(1, 960, false), Bytes::from_static(b"b"), now);
+        // Only 2 frames buffered but DEFAULT_DEPTH_FRAMES (3) are required
+        // to prime the cursor.
+        assert!(matches!(jb.tick(), PlayoutAction::Wait));
+    }
+
+    #[test]
+    fn insert_reorders_and_tick_plays_in_sequence_order() {
+        let mut jb = JitterBuffer::new();
+        let now = Instant::now();
+        jb.insert(&hdr(2, 2 * 960, false), Bytes::from_static(b"c"), now);
+        jb.insert(&hdr(0, 0, false), Bytes::from_static(b"a"), now);
+        jb.insert(&hdr(1, 960, false), Bytes::from_static(b"b"), now);
+
+        for expected in [&b"a"[..], &b"b"[..], &b"c"[..]] {
+            match jb.tick() {
+                PlayoutAction::Play(p) => assert_eq!(p, Bytes::from_static(expected)),
+                _ => panic!("expected Play"),
+            }
+        }
+    }
+
+    #[test]
+    fn insert_drops_late_arrivals_as_the_cursor_has_passed_them() {
+        let mut jb = JitterBuffer::new();
+        let now = Instant::now();
+        for seq in 0..3 {
+            jb.insert(&hdr(seq, seq * 960, false), Bytes::from_static(b"x"), now);
+        }
+        jb.tick(); // primes the cursor at 0, consumes it, cursor becomes 1
+        jb.insert(&hdr(0, 0, false), Bytes::from_static(b"late"), now);
+        assert_eq!(jb.stats().late, 1);
+    }
+
+    #[test]
+    fn tick_reconstructs_a_lost_frame_via_fec_from_the_next_packet() {
+        let mut jb = JitterBuffer::new();
+        let now = Instant::now();
+        for seq in 0..3 {
+            jb.insert(&hdr(seq, seq * 960, false), Bytes::from_static(b"x"), now);
+        }
+        jb.tick();
+        jb.tick();
+        jb.tick(); // drains 0,1,2; cursor now at 3
+        // Sequence 3 never arrives, but 4 does — its FEC redundancy covers it.
+        jb.insert(&hdr(4, 4 * 960, false), Bytes::from_static(b"fec-carrier"), now);
+        match jb.tick() {
+            PlayoutAction::Fec(p) => assert_eq!(p, Bytes::from_static(b"fec-carrier")),
+            _ => panic!("expected Fec"),
+        }
+        assert_eq!(jb.stats().lost, 1);
+    }
+
+    #[test]
+    fn tick_emits_silence_for_a_dtx_marked_gap_instead_of_counting_loss() {
+        let mut jb = JitterBuffer::new();
+        let now = Instant::now();
+        for seq in 0..3 {
+            jb.insert(&hdr(seq, seq * 960, false), Bytes::from_static(b"x"), now);
+        }
+        jb.tick();
+        jb.tick();
+        jb.tick(); // cursor now at 3
+        // Sequence 3 itself never arrives, but 4 arrives marked dtx,
+        // signalling the sender went silent right at this gap.
+        jb.insert(&hdr(4, 4 * 960, true), Bytes::from_static(b"sid"), now);
+        assert!(matches!(jb.tick(), PlayoutAction::Silence));
+        assert_eq!(jb.stats().lost, 0);
+    }
+
+    #[test]
+    fn tick_keeps_reading_silence_through_an_empty_dtx_gap() {
+        let mut jb = JitterBuffer::new();
+        let now = Instant::now();
+        for seq in 0..3 {
+            jb.insert(&hdr(seq, seq * 960, true), Bytes::from_static(b"sid"), now);
+        }
+        // Drain the three primed dtx frames: each plays as Silence.
+        for _ in 0..3 {
+            assert!(matches!(jb.tick(), PlayoutAction::Silence));
+        }
+        // Nothing at all is buffered for this slot, but the buffer is still
+        // inside the silence span it just recognized — stays Silence rather
+        // than a concealment / loss.
+        assert!(matches!(jb.tick(), PlayoutAction::Silence));
+        assert_eq!(jb.stats().lost, 0);
+    }
+
+    #[test]
+    fn silence_decays_the_playout_depth_without_touching_the_jitter_estimate() {
+        let mut jb = JitterBuffer::new();
+        let now = Instant::now();
+        for seq in 0..3 {
+            jb.insert(&hdr(seq, seq * 960, true), Bytes::from_static(b"sid"), now);
+        }
+        let before = jb.target_depth_frames;
+        let before_jitter = jb.jitter_samples;
+        jb.tick(); // first dtx frame plays as Silence, decaying the depth
+        assert_eq!(
+            jb.target_depth_frames,
+            ((before as f64 * SILENCE_DECAY) as u32).max(MIN_DEPTH_MS / FRAME_MS)
+        );
+        assert_eq!(jb.jitter_samples, before_jitter);
+    }
+
+    #[test]
+    fn adaptive_depth_grows_with_sustained_inter_arrival_jitter() {
+        let mut jb = JitterBuffer::new();
+        let mut now = Instant::now();
+        let mut seq = 0u32;
+        let mut timestamp = 0u32;
+        // Every arrival lands 40ms apart on the wall clock but only 20ms
+        // (960 samples) apart on the sender's clock — a constant 20ms of
+        // extra one-way delay the EWMA should converge on.
+        for _ in 0..200 {
+            jb.insert(&hdr(seq, timestamp, false), Bytes::from_static(b"x"), now);
+            seq += 1;
+            timestamp += 960;
+            now += Duration::from_millis(40);
+        }
+        let jitter_ms = jb.stats().jitter_ms;
+        assert!((18..=22).contains(&jitter_ms), "jitter_ms={jitter_ms}");
+        // target_ms = jitter_ms * DEPTH_JITTER_MULTIPLIER (4), in 20ms frames.
+        assert_eq!(jb.target_depth_frames, 4);
+    }
+
+    #[test]
+    fn adaptive_depth_stays_at_the_floor_on_a_clean_link() {
+        let mut jb = JitterBuffer::new();
+        let mut now = Instant::now();
+        let mut seq = 0u32;
+        let mut timestamp = 0u32;
+        // Wall-clock spacing matches the sender's clock exactly — no jitter
+        // to adapt to, so the depth should settle at the 20ms floor (1 frame).
+        for _ in 0..50 {
+            jb.insert(&hdr(seq, timestamp, false), Bytes::from_static(b"x"), now);
+            seq += 1;
+            timestamp += 960;
+            now += Duration::from_millis(20);
+        }
+        assert_eq!(jb.stats().jitter_ms, 0);
+        assert_eq!(jb.target_depth_frames, (MIN_DEPTH_MS / FRAME_MS).max(1));
+    }
+}
@@ -1,5 +1,7 @@
 mod audio;
+mod bwe;
 mod codec;
+mod jitter;
 mod quic;
 mod state;
 mod video;
@@ -17,9 +19,11 @@ enum MediaCommand {
         token: String,
         room_id: u32,
         user_id: u32,
-        cert_der: Option<Vec<u8>>,
-        idle_timeout_secs: u64,
-        datagram_buffer_size: usize,
+        trust: quic::TrustMode,
+        client_auth: Option<quic::ClientAuthCert>,
+        transport_tuning: quic::TransportTuning,
+        transport_mode: String,
+        dtx_enabled: bool,
     },
     Disconnect,
     SetMute(bool),
@@ -34,6 +38,14 @@ enum MediaEvent {
     ConnectFailed(String),
     Reconnecting { attempt: u32, delay_secs: u64 },
     AudioError(String),
+    /// Periodic snapshot of the jitter buffer's call-quality counters, so
+    /// Python can surface call quality without polling internals directly.
+    CallStats {
+        buffered_frames: u32,
+        jitter_ms: u32,
+        lost: u32,
+        late: u32,
+    },
 }
 
 impl MediaEvent {
@@ -46,6 +58,10 @@ impl MediaEvent {
                 ("reconnecting".into(), format!("attempt={attempt},delay={delay_secs}"))
             }
             MediaEvent::AudioError(msg) => ("audio_error".into(), msg.clone()),
+            MediaEvent::CallStats { buffered_frames, jitter_ms, lost, late } => (
+                "call_stats".into(),
+                format!("buffered_frames={buffered_frames},jitter_ms={jitter_ms},lost={lost},late={late}"),
+            ),
         }
     }
 }
@@ -125,16 +141,111 @@ impl VoxMediaClient {
     }
 
     /// Connect to a voice room via the SFU.
-    #[pyo3(signature = (url, token, room_id, user_id, cert_der=None, idle_timeout_secs=30, datagram_buffer_size=65535))]
-    fn connect(&self, url: &str, token: &str, room_id: u32, user_id: u32, cert_der: Option<Vec<u8>>, idle_timeout_secs: u64, datagram_buffer_size: usize) -> PyResult<()> {
+    ///
+    /// `transport_mode` is either `"datagram"` (every layer chunk, keyframes
+    /// included, rides unreliable datagrams) or `"hybrid"` (keyframes are
+    /// sent as their own reliable QUIC stream so a lost packet can't stall a
+    /// fresh decode point; deltas stay on datagrams either way).
+    ///
+    /// `dtx_enabled` turns on discontinuous transmission for the outgoing
+    /// Opus encoder: while the local microphone is silent, full 20ms frames
+    /// stop going out entirely except for periodic comfort-noise updates,
+    /// cutting uplink bandwidth in a quiet multi-party room. Off by default
+    /// since it trades a small amount of voice-activity leakage (when speech
+    /// resumes) for the bandwidth savings.
+    ///
+    /// `spki_pins`, `cert_der`, and `native_roots` select how the server
+    /// certificate is verified, in that priority order (an empty `spki_pins`
+    /// list counts as not given): `spki_pins` pins base64-encoded SHA-256
+    /// digests of the certificate's public key (current + backup, as with
+    /// HPKP) and survives certificate renewal; `cert_der` pins the server's
+    /// exact self-signed certificate bytes; `native_roots` trusts the host OS's trust store instead of the
+    /// bundled Mozilla roots, for a CA-signed SFU behind a corporate MITM
+    /// proxy or a private CA installed at the OS level. If none are given,
+    /// the bundled CA roots are used.
+    ///
+    /// `keep_alive_interval_secs` (`None` disables it) keeps an otherwise
+    /// idle connection from tripping `idle_timeout_secs` while a
+    /// participant is muted or silenced by DTX. `datagram_buffer_size` and
+    /// `datagram_send_buffer_size` size the QUIC datagram queues to hold
+    /// roughly a jitter window of frames. `congestion_controller` is
+    /// `"cubic"` (Quinn's default) or `"bbr"`, which paces to the path's
+    /// estimated bandwidth-delay product instead of growing until loss —
+    /// usually lower queueing delay for a steady stream of media datagrams.
+    ///
+    /// `client_cert_der`/`client_key_der` present a client certificate
+    /// during the handshake (mutual TLS), letting the SFU cryptographically
+    /// identify the connecting user for per-user/per-room admission instead
+    /// of trusting the unauthenticated `user_id` in `MediaHeader`. Both must
+    /// be given together or not at all; `client_key_der` accepts PKCS#8,
+    /// SEC1, or PKCS#1 DER. Independent of — and composes with — `cert_der`/
+    /// `spki_pins`/`native_roots`, which verify the *server's* certificate.
+    #[pyo3(signature = (url, token, room_id, user_id, cert_der=None, spki_pins=None, native_roots=false, client_cert_der=None, client_key_der=None, idle_timeout_secs=30, datagram_buffer_size=65535, datagram_send_buffer_size=65535, keep_alive_interval_secs=5, congestion_controller="cubic", transport_mode="datagram", dtx_enabled=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        &self,
+        url: &str,
+        token: &str,
+        room_id: u32,
+        user_id: u32,
+        cert_der: Option<Vec<u8>>,
+        spki_pins: Option<Vec<String>>,
+        native_roots: bool,
+        client_cert_der: Option<Vec<u8>>,
+        client_key_der: Option<Vec<u8>>,
+        idle_timeout_secs: u64,
+        datagram_buffer_size: usize,
+        datagram_send_buffer_size: usize,
+        keep_alive_interval_secs: Option<u64>,
+        congestion_controller: &str,
+        transport_mode: &str,
+        dtx_enabled: bool,
+    ) -> PyResult<()> {
+        let trust = match (spki_pins, cert_der, native_roots) {
+            (Some(pins), _, _) if !pins.is_empty() => {
+                let pins = pins
+                    .iter()
+                    .map(|p| quic::decode_spki_pin(p))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e))?;
+                quic::TrustMode::Spki(pins)
+            }
+            (_, Some(der), _) => quic::TrustMode::ExactDer(der),
+            (_, None, true) => quic::TrustMode::NativeRoots,
+            (_, None, false) => quic::TrustMode::WebPkiRoots,
+        };
+        let client_auth = match (client_cert_der, client_key_der) {
+            (Some(cert), Some(key)) => Some(
+                quic::parse_client_auth_cert(cert, key)
+                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string()))?,
+            ),
+            (None, None) => None,
+            _ => {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                    "client_cert_der and client_key_der must be given together",
+                ))
+            }
+        };
+        let congestion_controller: quic::CongestionController = congestion_controller
+            .parse()
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyValueError, _>(e))?;
+        let transport_tuning = quic::TransportTuning {
+            keep_alive_interval_secs,
+            max_idle_timeout_secs: idle_timeout_secs,
+            datagram_receive_buffer_size: datagram_buffer_size,
+            datagram_send_buffer_size,
+            congestion_controller,
+        };
         self.send_cmd(MediaCommand::Connect {
             url: url.to_string(),
             token: token.to_string(),
             room_id,
             user_id,
-            cert_der,
-            idle_timeout_secs,
-            datagram_buffer_size,
+            trust,
+            client_auth,
+            transport_tuning,
+            transport_mode: transport_mode.to_string(),
+            dtx_enabled,
         })
     }
 
@@ -155,15 +266,10 @@ impl VoxMediaClient {
         self.send_cmd(MediaCommand::SetDeaf(deafened))
     }
 
-    /// Enable or disable video.
+    /// Enable or disable camera capture and video publishing.
     fn set_video(&mut self, enabled: bool) -> PyResult<()> {
-        if enabled {
-            return Err(PyErr::new::<pyo3::exceptions::PyNotImplementedError, _>(
-                "Video is not yet supported",
-            ));
-        }
-        self.video = false;
-        Ok(())
+        self.video = enabled;
+        self.send_cmd(MediaCommand::SetVideo(enabled))
     }
 
     /// Poll for the next event from the media runtime.
@@ -3,9 +3,12 @@
 //! Connects to the SFU using the same packet format as vox-sfu,
 //! sends/receives media frames over QUIC datagrams.
 
+use base64::Engine;
 use bytes::{BufMut, Bytes, BytesMut};
 use quinn::ClientConfig;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// ALPN protocol identifier — must match the SFU server.
 const ALPN_PROTOCOL: &[u8] = b"vox-media/1";
@@ -17,6 +20,46 @@ pub const HEADER_SIZE: usize = 22;
 pub const MEDIA_TYPE_AUDIO: u8 = 0;
 pub const MEDIA_TYPE_VIDEO: u8 = 1;
 
+// Codec id values (MediaHeader.codec_id)
+pub const CODEC_OPUS: u8 = 1;
+pub const CODEC_VP9: u8 = 2;
+
+/// Size of the fragment sub-header prepended to a video datagram's payload
+/// when a single encoded layer chunk doesn't fit in one QUIC datagram.
+pub const FRAGMENT_HEADER_SIZE: usize = 4;
+
+/// Discriminator byte sent as the first byte of every unidirectional QUIC
+/// stream this client opens, so the SFU can tell a long-lived bandwidth
+/// feedback stream apart from a one-shot keyframe object stream.
+pub const STREAM_KIND_FEEDBACK: u8 = 0;
+pub const STREAM_KIND_KEYFRAME_OBJECT: u8 = 1;
+
+/// How video is carried over QUIC.
+///
+/// `Datagram` sends every layer chunk (deltas and keyframes alike) as
+/// unreliable, fragmented datagrams — lowest latency, but a lost keyframe
+/// stalls that layer until the next one. `Hybrid` keeps deltas on datagrams
+/// but sends each independently-decodable unit (a keyframe, i.e. anything
+/// with `FLAG_KEYFRAME`) as its own ordered, retransmitted QUIC stream, so
+/// loss can't stall a fresh decode point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportMode {
+    Datagram,
+    Hybrid,
+}
+
+impl std::str::FromStr for TransportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "datagram" => Ok(TransportMode::Datagram),
+            "hybrid" => Ok(TransportMode::Hybrid),
+            other => Err(format!("unknown transport mode: {other}")),
+        }
+    }
+}
+
 // Flag bits (byte 3) — mirrors vox-sfu header.rs
 pub const FLAG_KEYFRAME: u8 = 0b1000_0000;
 pub const FLAG_END_OF_FRAME: u8 = 0b0100_0000;
@@ -114,21 +157,32 @@ pub struct OutFrame {
 }
 
 impl OutFrame {
-    /// Build an audio frame with sensible defaults.
-    pub fn audio(room_id: u32, user_id: u32, codec_id: u8, seq: u32, timestamp: u32, payload: Bytes) -> Self {
+    /// Build an audio frame with sensible defaults. `fec_enabled` mirrors
+    /// whether the encoder embedded in-band FEC redundancy in `payload`, so
+    /// the receiver's `FLAG_FEC` check (reserved for future use — today's
+    /// jitter buffer already tries `decode_fec` whenever the next packet is
+    /// available, flag or not) has something to read. `dtx` marks `payload`
+    /// as a DTX comfort-noise update rather than a full voice frame, so the
+    /// receiver's jitter buffer plays out silence instead of treating the
+    /// surrounding gap as loss.
+    pub fn audio(room_id: u32, user_id: u32, codec_id: u8, seq: u32, timestamp: u32, fec_enabled: bool, dtx: bool, payload: Bytes) -> Self {
+        let mut flags = FLAG_END_OF_FRAME;
+        if fec_enabled {
+            flags |= FLAG_FEC;
+        }
         OutFrame {
             header: MediaHeader {
                 version: PROTOCOL_VERSION,
                 media_type: MEDIA_TYPE_AUDIO,
                 codec_id,
-                flags: FLAG_END_OF_FRAME,
+                flags,
                 room_id,
                 user_id,
                 sequence: seq,
                 timestamp,
                 spatial_id: 0,
                 temporal_id: 0,
-                dtx: false,
+                dtx,
             },
             payload,
         }
@@ -141,6 +195,200 @@ impl OutFrame {
         buf.extend_from_slice(&self.payload);
         buf.freeze()
     }
+
+    /// Build a video frame for one simulcast layer. `layer` is carried in
+    /// `spatial_id`; temporal layering is not used for the non-SVC codec
+    /// path and is left at 0.
+    pub fn video(
+        room_id: u32,
+        user_id: u32,
+        codec_id: u8,
+        seq: u32,
+        timestamp: u32,
+        layer: u8,
+        is_keyframe: bool,
+        payload: Bytes,
+    ) -> Self {
+        let mut flags = FLAG_END_OF_FRAME;
+        if is_keyframe {
+            flags |= FLAG_KEYFRAME;
+        }
+        OutFrame {
+            header: MediaHeader {
+                version: PROTOCOL_VERSION,
+                media_type: MEDIA_TYPE_VIDEO,
+                codec_id,
+                flags,
+                room_id,
+                user_id,
+                sequence: seq,
+                timestamp,
+                spatial_id: layer,
+                temporal_id: 0,
+                dtx: false,
+            },
+            payload,
+        }
+    }
+}
+
+/// Prefix carried immediately after `MediaHeader` in a fragmented video
+/// datagram so the receiver can reassemble an encoded chunk that didn't fit
+/// in a single QUIC datagram.
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentHeader {
+    /// Identifies which encoded chunk this fragment belongs to. Scoped to
+    /// (room_id, user_id, spatial_id) and safe to wrap.
+    pub frame_id: u16,
+    /// Zero-based position of this fragment within the chunk.
+    pub fragment_index: u8,
+    /// True if this is the last fragment of the chunk.
+    pub last_fragment: bool,
+}
+
+impl FragmentHeader {
+    pub fn encode(&self) -> [u8; FRAGMENT_HEADER_SIZE] {
+        let mut buf = [0u8; FRAGMENT_HEADER_SIZE];
+        buf[0..2].copy_from_slice(&self.frame_id.to_be_bytes());
+        buf[2] = self.fragment_index;
+        buf[3] = self.last_fragment as u8;
+        buf
+    }
+
+    pub fn parse(data: &[u8]) -> Option<(Self, &[u8])> {
+        if data.len() < FRAGMENT_HEADER_SIZE {
+            return None;
+        }
+        let hdr = FragmentHeader {
+            frame_id: u16::from_be_bytes([data[0], data[1]]),
+            fragment_index: data[2],
+            last_fragment: data[3] != 0,
+        };
+        Some((hdr, &data[FRAGMENT_HEADER_SIZE..]))
+    }
+}
+
+/// Split an encoded video chunk into datagram-sized fragments, each prefixed
+/// with a `MediaHeader` and a `FragmentHeader`. `max_datagram_payload` is the
+/// space available after the fixed `MediaHeader` (i.e. the path MTU minus
+/// `HEADER_SIZE`).
+pub fn fragment_video_frame(
+    room_id: u32,
+    user_id: u32,
+    codec_id: u8,
+    seq_start: u32,
+    timestamp: u32,
+    layer: u8,
+    is_keyframe: bool,
+    frame_id: u16,
+    data: &Bytes,
+    max_datagram_payload: usize,
+) -> Vec<Bytes> {
+    let chunk_size = max_datagram_payload.saturating_sub(FRAGMENT_HEADER_SIZE).max(1);
+    let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+    let total = chunks.len().max(1);
+
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let frag = FragmentHeader {
+                frame_id,
+                fragment_index: i as u8,
+                last_fragment: i + 1 == total,
+            };
+            let mut payload = BytesMut::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            payload.put_slice(&frag.encode());
+            payload.extend_from_slice(chunk);
+
+            OutFrame::video(
+                room_id,
+                user_id,
+                codec_id,
+                seq_start.wrapping_add(i as u32),
+                timestamp,
+                layer,
+                is_keyframe,
+                payload.freeze(),
+            )
+            .encode()
+        })
+        .collect()
+}
+
+/// Upper bound on simultaneously in-flight partial frames (across every
+/// sender/layer) before the oldest is evicted. Datagrams can drop a
+/// fragment entirely, and without a bound a frame missing just one
+/// fragment would sit in `partial` for the rest of the session.
+const MAX_PARTIAL_FRAMES: usize = 64;
+
+#[derive(Default)]
+struct PartialFrame {
+    slots: Vec<Option<Bytes>>,
+    /// Total fragment count, known once the fragment marked
+    /// `last_fragment` is seen — recorded independently of arrival order,
+    /// since QUIC datagram reordering (the reason fragmentation exists in
+    /// the first place) can deliver it before earlier fragments.
+    expected_total: Option<usize>,
+}
+
+/// Reassembles fragmented video chunks per (sender, layer) back into
+/// complete encoded frames.
+#[derive(Default)]
+pub struct VideoReassembler {
+    partial: std::collections::HashMap<(u32, u8, u16), PartialFrame>,
+    /// Insertion order of `partial`'s keys, oldest first, so eviction can
+    /// drop the oldest in-flight frame once `MAX_PARTIAL_FRAMES` is exceeded.
+    order: std::collections::VecDeque<(u32, u8, u16)>,
+}
+
+impl VideoReassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one received (already-stripped) fragment. Returns the
+    /// reassembled chunk once every fragment for a frame has arrived,
+    /// regardless of which one completed it.
+    pub fn push(&mut self, sender_id: u32, layer: u8, frame: &InFrame) -> Option<Bytes> {
+        let (frag, rest) = FragmentHeader::parse(&frame.payload)?;
+        let key = (sender_id, layer, frag.frame_id);
+
+        let is_new_frame = !self.partial.contains_key(&key);
+        let entry = self.partial.entry(key).or_default();
+        let idx = frag.fragment_index as usize;
+        if entry.slots.len() <= idx {
+            entry.slots.resize(idx + 1, None);
+        }
+        entry.slots[idx] = Some(Bytes::copy_from_slice(rest));
+        if frag.last_fragment {
+            entry.expected_total = Some(idx + 1);
+        }
+
+        if is_new_frame {
+            self.order.push_back(key);
+            while self.order.len() > MAX_PARTIAL_FRAMES {
+                if let Some(stale_key) = self.order.pop_front() {
+                    self.partial.remove(&stale_key);
+                }
+            }
+        }
+
+        let complete = self.partial.get(&key).is_some_and(|entry| {
+            entry.expected_total == Some(entry.slots.len()) && entry.slots.iter().all(Option::is_some)
+        });
+        if !complete {
+            return None;
+        }
+
+        let entry = self.partial.remove(&key).unwrap();
+        self.order.retain(|k| *k != key);
+        let mut out = BytesMut::new();
+        for part in entry.slots {
+            out.extend_from_slice(&part.unwrap());
+        }
+        Some(out.freeze())
+    }
 }
 
 /// Inbound media frame received from the SFU.
@@ -157,38 +405,305 @@ impl InFrame {
     }
 }
 
-/// Build a QUIC client config.
+/// Encode a keyframe-object QUIC stream: the stream-kind discriminator, a
+/// monotonically increasing group id (so the SFU can tell which of this
+/// peer's in-flight keyframe streams for a layer is newest), the frame's
+/// `MediaHeader`, then the raw encoded payload. Sent as the entire contents
+/// of its own unidirectional stream — the stream's ordering and FIN already
+/// give reassembly and end-of-object framing for free, so unlike
+/// `fragment_video_frame` no `FragmentHeader` is needed here.
+pub fn encode_keyframe_object(group_id: u32, header: &MediaHeader, payload: &[u8]) -> Bytes {
+    let header_bytes = header.encode();
+    let mut buf = BytesMut::with_capacity(1 + 4 + HEADER_SIZE + payload.len());
+    buf.put_u8(STREAM_KIND_KEYFRAME_OBJECT);
+    buf.put_u32(group_id);
+    buf.put_slice(&header_bytes);
+    buf.extend_from_slice(payload);
+    buf.freeze()
+}
+
+/// Encode the auth datagram sent as the very first datagram on a new
+/// connection: the media token followed by this client's supported audio
+/// codec ids, in priority order, so the SFU only forwards codecs this peer
+/// can actually decode.
+///
+/// Wire layout: `[token_len: u8][token bytes][codec_count: u8][codec ids]`.
+pub fn encode_auth_frame(token: &str, codecs: &[u8]) -> Bytes {
+    let token_bytes = token.as_bytes();
+    let mut buf = BytesMut::with_capacity(1 + token_bytes.len() + 1 + codecs.len());
+    buf.put_u8(token_bytes.len() as u8);
+    buf.put_slice(token_bytes);
+    buf.put_u8(codecs.len() as u8);
+    buf.put_slice(codecs);
+    buf.freeze()
+}
+
+/// The `rustls::crypto::CryptoProvider` `make_client_config` uses when the
+/// caller doesn't supply one of its own — ring, matching this crate's prior
+/// hardwired behavior.
+pub fn default_crypto_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// A `rustls::KeyLog` that exports TLS secrets to the file named by the
+/// `SSLKEYLOGFILE` environment variable, for decrypting captured QUIC media
+/// traffic in Wireshark. Returns `None` (no-op, never enabled by accident)
+/// if the variable isn't set, so production deployments don't need to
+/// remember to disable it explicitly.
+pub fn key_log_from_env() -> Option<Arc<dyn rustls::KeyLog>> {
+    if std::env::var_os("SSLKEYLOGFILE").is_some() {
+        Some(Arc::new(rustls::KeyLogFile::new()))
+    } else {
+        None
+    }
+}
+
+/// How `make_client_config` verifies the server certificate.
+///
+/// `WebPkiRoots` (the default) trusts the bundled Mozilla root set —
+/// appropriate for a publicly CA-signed Vox SFU. `NativeRoots` instead
+/// trusts whatever the host OS has installed, so a deployment behind a
+/// corporate MITM proxy or with a private CA pushed out at the OS level can
+/// connect without re-bundling roots into the client. `ExactDer` pins the
+/// literal end-entity certificate bytes, which is exact but breaks on every
+/// renewal even when the underlying key hasn't changed. `Spki` instead pins
+/// the SHA-256 digest of the certificate's SubjectPublicKeyInfo against a
+/// current + backup pin set (as with HPKP), so the SFU operator can rotate
+/// certificates — including switching CAs — without shipping new clients,
+/// as long as the key is reused or a backup pin for the new key was already
+/// deployed.
+#[derive(Debug, Clone, Default)]
+pub enum TrustMode {
+    #[default]
+    WebPkiRoots,
+    NativeRoots,
+    ExactDer(Vec<u8>),
+    Spki(Vec<[u8; 32]>),
+}
+
+/// A client certificate chain and matching private key for mutual TLS,
+/// letting the SFU cryptographically identify the connecting user instead
+/// of trusting the unauthenticated `user_id` embedded in `MediaHeader`.
+#[derive(Debug, Clone)]
+pub struct ClientAuthCert {
+    pub cert_chain: Vec<rustls::pki_types::CertificateDer<'static>>,
+    pub key: rustls::pki_types::PrivateKeyDer<'static>,
+}
+
+/// Parse a single DER-encoded client certificate and a DER-encoded private
+/// key (PKCS#8, SEC1, or PKCS#1) into a `ClientAuthCert`, surfacing a
+/// malformed key as a typed error rather than panicking.
+pub fn parse_client_auth_cert(
+    cert_der: Vec<u8>,
+    key_der: Vec<u8>,
+) -> Result<ClientAuthCert, Box<dyn std::error::Error>> {
+    let key = rustls::pki_types::PrivateKeyDer::try_from(key_der)
+        .map_err(|e| format!("invalid client private key: {e}"))?;
+    Ok(ClientAuthCert {
+        cert_chain: vec![rustls::pki_types::CertificateDer::from(cert_der)],
+        key,
+    })
+}
+
+/// Build a QUIC client config against the given crypto provider — ring by
+/// default (`default_crypto_provider`), or aws-lc-rs/BoringSSL-backed for
+/// FIPS deployments or cipher/signature-scheme parity with a particular
+/// browser/WebRTC stack.
+///
+/// `key_log`, if given, is installed on the `rustls::ClientConfig` so TLS
+/// secrets are exported for the connection's lifetime — see
+/// `key_log_from_env`. Leave it `None` in production; it exists purely to
+/// let Wireshark decrypt captured QUIC-carried media traffic in development.
+///
+/// `client_auth`, if given, presents a client certificate during the
+/// handshake (mutual TLS) for SFU-side per-user/per-room admission control.
+/// Composes with every `TrustMode` — root selection and client
+/// authentication are independent axes.
 ///
-/// - `None` → CA-signed mode: uses Mozilla root certificates.
-/// - `Some(der)` → Self-signed mode: pins to the exact certificate DER bytes.
-pub fn make_client_config(cert_der: Option<Vec<u8>>) -> ClientConfig {
-    let mut crypto = match cert_der {
-        None => {
+/// Errors if `provider` doesn't support the protocol versions/cipher suites
+/// this crate needs — a real possibility for a restricted FIPS provider,
+/// unlike the always-complete default ring provider.
+pub fn make_client_config(
+    trust: TrustMode,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+    key_log: Option<Arc<dyn rustls::KeyLog>>,
+    client_auth: Option<ClientAuthCert>,
+) -> Result<ClientConfig, Box<dyn std::error::Error>> {
+    let mut crypto = match trust {
+        TrustMode::WebPkiRoots => {
             let mut roots = rustls::RootCertStore::empty();
             roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-            rustls::ClientConfig::builder()
-                .with_root_certificates(roots)
-                .with_no_client_auth()
+            let builder = rustls::ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots);
+            finish_client_config(builder, client_auth)?
+        }
+        TrustMode::NativeRoots => {
+            let mut roots = rustls::RootCertStore::empty();
+            match rustls_native_certs::load_native_certs() {
+                Ok(certs) => {
+                    for cert in certs {
+                        if roots.add(cert).is_err() {
+                            tracing::warn!("Skipping unparseable native root certificate");
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load native root certificates: {} — falling back to an empty trust store", e);
+                }
+            }
+            if roots.is_empty() {
+                tracing::warn!("Native trust store is empty; no certificate will verify");
+            }
+            let builder = rustls::ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots);
+            finish_client_config(builder, client_auth)?
+        }
+        TrustMode::ExactDer(der) => {
+            let verifier = Arc::new(PinnedCertVerifier { der, provider: provider.clone() });
+            let builder = rustls::ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()?
+                .dangerous()
+                .with_custom_certificate_verifier(verifier);
+            finish_client_config(builder, client_auth)?
         }
-        Some(der) => {
-            rustls::ClientConfig::builder()
+        TrustMode::Spki(pins) => {
+            let verifier = Arc::new(SpkiPinnedCertVerifier { pins, provider: provider.clone() });
+            let builder = rustls::ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()?
                 .dangerous()
-                .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier { der }))
-                .with_no_client_auth()
+                .with_custom_certificate_verifier(verifier);
+            finish_client_config(builder, client_auth)?
         }
     };
     crypto.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
-    ClientConfig::new(Arc::new(
-        quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap(),
-    ))
+    if let Some(key_log) = key_log {
+        crypto.key_log = key_log;
+    }
+    Ok(ClientConfig::new(Arc::new(
+        quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
+    )))
+}
+
+/// Finish a `rustls::ConfigBuilder` once root/verifier selection is done,
+/// presenting `client_auth`'s certificate for mutual TLS if given and
+/// falling back to no client authentication otherwise. Shared by every
+/// `TrustMode` arm so root selection and client auth stay independent axes.
+fn finish_client_config(
+    builder: rustls::ConfigBuilder<rustls::ClientConfig, rustls::client::WantsClientCert>,
+    client_auth: Option<ClientAuthCert>,
+) -> Result<rustls::ClientConfig, Box<dyn std::error::Error>> {
+    match client_auth {
+        None => Ok(builder.with_no_client_auth()),
+        Some(auth) => Ok(builder.with_client_auth_cert(auth.cert_chain, auth.key)?),
+    }
+}
+
+/// Congestion controller `TransportTuning` attaches to the QUIC connection.
+/// `Cubic` is Quinn's default, tuned for bulk throughput. `Bbr` paces
+/// sending to its estimate of the path's bandwidth-delay product instead of
+/// growing the window until loss, which tends to keep queueing delay — and
+/// so jitter — lower for a steady stream of small media datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionController {
+    #[default]
+    Cubic,
+    Bbr,
+}
+
+impl std::str::FromStr for CongestionController {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cubic" => Ok(CongestionController::Cubic),
+            "bbr" => Ok(CongestionController::Bbr),
+            other => Err(format!("unknown congestion controller: {other}")),
+        }
+    }
+}
+
+/// Media-appropriate knobs for the QUIC transport, as opposed to Quinn's
+/// bulk-stream-tuned defaults.
+#[derive(Debug, Clone)]
+pub struct TransportTuning {
+    /// How often to send a keep-alive on an otherwise idle connection, so a
+    /// participant muted or silenced by DTX doesn't sit idle long enough to
+    /// trip `max_idle_timeout`. `None` disables keep-alives.
+    pub keep_alive_interval_secs: Option<u64>,
+    /// Idle timeout before Quinn gives up on the connection.
+    pub max_idle_timeout_secs: u64,
+    /// Datagram receive buffer size, in bytes — sized to hold roughly a
+    /// jitter window's worth of in-flight frames rather than Quinn's
+    /// bulk-stream default.
+    pub datagram_receive_buffer_size: usize,
+    /// Datagram send buffer size, in bytes.
+    pub datagram_send_buffer_size: usize,
+    pub congestion_controller: CongestionController,
+}
+
+impl Default for TransportTuning {
+    fn default() -> Self {
+        TransportTuning {
+            keep_alive_interval_secs: Some(5),
+            max_idle_timeout_secs: 30,
+            datagram_receive_buffer_size: 1_000_000,
+            datagram_send_buffer_size: 1_000_000,
+            congestion_controller: CongestionController::default(),
+        }
+    }
+}
+
+/// Build a `quinn::TransportConfig` from `tuning`.
+pub fn build_transport_config(
+    tuning: &TransportTuning,
+) -> Result<quinn::TransportConfig, Box<dyn std::error::Error>> {
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(tuning.keep_alive_interval_secs.map(Duration::from_secs));
+    transport.max_idle_timeout(Some(quinn::IdleTimeout::try_from(Duration::from_secs(
+        tuning.max_idle_timeout_secs,
+    ))?));
+    transport.datagram_receive_buffer_size(Some(tuning.datagram_receive_buffer_size));
+    transport.datagram_send_buffer_size(tuning.datagram_send_buffer_size);
+    match tuning.congestion_controller {
+        CongestionController::Cubic => {
+            transport.congestion_controller_factory(Arc::new(
+                quinn::congestion::CubicConfig::default(),
+            ));
+        }
+        CongestionController::Bbr => {
+            transport.congestion_controller_factory(Arc::new(
+                quinn::congestion::BbrConfig::default(),
+            ));
+        }
+    }
+    Ok(transport)
+}
+
+/// `make_client_config` plus a `TransportConfig` built from `tuning` — the
+/// entry point callers should use instead of reaching into Quinn directly
+/// to get media-appropriate keep-alive, buffer sizing, and congestion
+/// control.
+pub fn make_client_config_with_transport(
+    trust: TrustMode,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+    tuning: &TransportTuning,
+    key_log: Option<Arc<dyn rustls::KeyLog>>,
+    client_auth: Option<ClientAuthCert>,
+) -> Result<ClientConfig, Box<dyn std::error::Error>> {
+    let mut client_config = make_client_config(trust, provider, key_log, client_auth)?;
+    client_config.transport_config(Arc::new(build_transport_config(tuning)?));
+    Ok(client_config)
 }
 
 /// Verifies the server certificate by comparing its raw DER bytes against a
-/// pinned value, then delegates signature verification to the default ring
-/// provider.
+/// pinned value, then delegates signature verification to whichever crypto
+/// provider `make_client_config` was built with.
 #[derive(Debug)]
 struct PinnedCertVerifier {
     der: Vec<u8>,
+    provider: Arc<rustls::crypto::CryptoProvider>,
 }
 
 impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
@@ -219,7 +734,77 @@ impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
             message,
             cert,
             dss,
-            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Verifies the server certificate by hashing its SubjectPublicKeyInfo and
+/// checking it against a configured pin set, then delegates signature
+/// verification to whichever crypto provider `make_client_config` was built
+/// with. Unlike `PinnedCertVerifier`, this survives certificate renewal as
+/// long as the key (or a deployed backup pin) is unchanged.
+#[derive(Debug)]
+struct SpkiPinnedCertVerifier {
+    pins: Vec<[u8; 32]>,
+    provider: Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for SpkiPinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        let (_, cert) = x509_parser::parse_x509_certificate(end_entity.as_ref()).map_err(|_| {
+            rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding)
+        })?;
+        let spki_der = cert.tbs_certificate.subject_pki.raw;
+        let digest: [u8; 32] = Sha256::digest(spki_der).into();
+
+        if self.pins.iter().any(|pin| *pin == digest) {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
         )
     }
 
@@ -233,13 +818,107 @@ impl rustls::client::danger::ServerCertVerifier for PinnedCertVerifier {
             message,
             cert,
             dss,
-            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+            &self.provider.signature_verification_algorithms,
         )
     }
 
     fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
-        rustls::crypto::ring::default_provider()
+        self.provider
             .signature_verification_algorithms
             .supported_schemes()
     }
 }
+
+/// Decode one base64-encoded SPKI SHA-256 pin (e.g. as provided by an
+/// operator alongside an HPKP `pin-sha256` value) into raw digest bytes.
+pub fn decode_spki_pin(pin: &str) -> Result<[u8; 32], String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(pin)
+        .map_err(|e| format!("invalid base64 SPKI pin: {e}"))?;
+    bytes
+        .try_into()
+        .map_err(|_| "SPKI pin must decode to exactly 32 bytes (SHA-256)".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(frame_id: u16, fragment_index: u8, last_fragment: bool, chunk: &[u8]) -> InFrame {
+        let frag = FragmentHeader {
+            frame_id,
+            fragment_index,
+            last_fragment,
+        };
+        let mut payload = BytesMut::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+        payload.put_slice(&frag.encode());
+        payload.extend_from_slice(chunk);
+        InFrame {
+            header: MediaHeader {
+                version: PROTOCOL_VERSION,
+                media_type: MEDIA_TYPE_VIDEO,
+                codec_id: 2,
+                flags: 0,
+                room_id: 1,
+                user_id: 1,
+                sequence: 0,
+                timestamp: 0,
+                spatial_id: 0,
+                temporal_id: 0,
+                dtx: false,
+            },
+            payload: payload.freeze(),
+        }
+    }
+
+    #[test]
+    fn reassembles_fragments_arriving_in_order() {
+        let mut reassembler = VideoReassembler::new();
+        assert_eq!(reassembler.push(1, 0, &fragment(1, 0, false, b"hel")), None);
+        assert_eq!(reassembler.push(1, 0, &fragment(1, 1, false, b"lo,")), None);
+        let out = reassembler.push(1, 0, &fragment(1, 2, true, b" world"));
+        assert_eq!(out, Some(Bytes::from_static(b"hello, world")));
+    }
+
+    #[test]
+    fn reassembles_fragments_arriving_out_of_order() {
+        let mut reassembler = VideoReassembler::new();
+        // The last fragment (and its total-count marker) arrives first —
+        // QUIC datagram reordering is the whole reason fragmentation exists.
+        assert_eq!(reassembler.push(1, 0, &fragment(1, 2, true, b" world")), None);
+        assert_eq!(reassembler.push(1, 0, &fragment(1, 0, false, b"hel")), None);
+        let out = reassembler.push(1, 0, &fragment(1, 1, false, b"lo,"));
+        assert_eq!(out, Some(Bytes::from_static(b"hello, world")));
+    }
+
+    #[test]
+    fn a_missing_fragment_never_completes_the_frame() {
+        let mut reassembler = VideoReassembler::new();
+        assert_eq!(reassembler.push(1, 0, &fragment(1, 0, false, b"hel")), None);
+        // Fragment index 1 never arrives; the last fragment reports a total
+        // of 3 slots, so the frame can never be declared complete.
+        assert_eq!(reassembler.push(1, 0, &fragment(1, 2, true, b" world")), None);
+    }
+
+    #[test]
+    fn different_senders_and_layers_reassemble_independently() {
+        let mut reassembler = VideoReassembler::new();
+        assert_eq!(reassembler.push(1, 0, &fragment(1, 0, true, b"a")), Some(Bytes::from_static(b"a")));
+        assert_eq!(reassembler.push(2, 0, &fragment(1, 0, true, b"b")), Some(Bytes::from_static(b"b")));
+        assert_eq!(reassembler.push(1, 1, &fragment(1, 0, true, b"c")), Some(Bytes::from_static(b"c")));
+    }
+
+    #[test]
+    fn evicts_the_oldest_partial_frame_once_the_bound_is_exceeded() {
+        let mut reassembler = VideoReassembler::new();
+        // Open MAX_PARTIAL_FRAMES + 1 distinct, never-completed frames from
+        // the same sender/layer; the oldest (frame_id 0) should be evicted
+        // to bound memory rather than waiting forever for its other half.
+        for frame_id in 0..=MAX_PARTIAL_FRAMES as u16 {
+            assert_eq!(reassembler.push(1, 0, &fragment(frame_id, 0, false, b"x")), None);
+        }
+        assert_eq!(reassembler.partial.len(), MAX_PARTIAL_FRAMES);
+        assert!(!reassembler.partial.contains_key(&(1, 0, 0)));
+        assert!(reassembler.partial.contains_key(&(1, 0, MAX_PARTIAL_FRAMES as u16)));
+    }
+}
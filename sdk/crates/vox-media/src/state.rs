@@ -1,18 +1,34 @@
 //! Media state machine — processes commands from Python.
 
-use crate::{audio, codec, push_event, quic, EventQueue, MediaCommand, MediaEvent};
+use crate::{audio, bwe, codec, jitter, push_event, quic, video, EventQueue, MediaCommand, MediaEvent};
 use bytes::Bytes;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
+/// Conservative per-datagram payload budget, matched to the default QUIC
+/// datagram MTU (quinn's 1200-byte initial MTU) minus the fixed
+/// `MediaHeader`.
+const MAX_DATAGRAM_PAYLOAD: usize = 1200 - quic::HEADER_SIZE;
+
+/// Audio codecs this client advertises at connect time, in priority order.
+const SUPPORTED_CODECS: [u8; 1] = [quic::CODEC_OPUS];
+
+/// Samples per audio frame: 20ms at 48kHz, matching `OpusEncoder`/`OpusDecoder`.
+const AUDIO_FRAME_SAMPLES: usize = 960;
+
 /// Maximum number of automatic reconnection attempts after a QUIC read error.
 const MAX_RECONNECT_ATTEMPTS: u32 = 5;
 /// Maximum backoff delay in seconds between reconnection attempts.
 const MAX_BACKOFF_SECS: u64 = 30;
 
+/// Playout ticks between `MediaEvent::CallStats` emissions (~1s at the 20ms
+/// playout cadence) — frequent enough to track a call's health without
+/// flooding the event queue.
+const STATS_EMIT_INTERVAL_TICKS: u32 = 50;
+
 /// Snapshot of connection parameters for automatic reconnection.
 #[derive(Clone)]
 struct ConnectParams {
@@ -20,22 +36,23 @@ struct ConnectParams {
     token: String,
     room_id: u32,
     user_id: u32,
-    cert_der: Option<Vec<u8>>,
-    idle_timeout_secs: u64,
-    datagram_buffer_size: usize,
+    trust: quic::TrustMode,
+    client_auth: Option<quic::ClientAuthCert>,
+    transport_tuning: quic::TransportTuning,
+    transport_mode: quic::TransportMode,
+    dtx_enabled: bool,
 }
 
 /// Active media session — all live resources.
 /// Dropping this struct tears down the QUIC connection, stops audio streams,
-/// and frees the Opus encoder/decoder automatically.
+/// and frees the negotiated codec automatically.
 struct ActiveSession {
     connection: quinn::Connection,
     room_id: u32,
     user_id: u32,
     sequence: u32,
     timestamp: u32,
-    encoder: codec::OpusEncoder,
-    decoder: codec::OpusDecoder,
+    codec: Box<dyn codec::AudioCodec>,
     _capture_stream: cpal::Stream,
     capture_rx: mpsc::UnboundedReceiver<Vec<i16>>,
     _playback_stream: cpal::Stream,
@@ -43,6 +60,36 @@ struct ActiveSession {
     muted: bool,
     deafened: bool,
     video: bool,
+    /// How video is carried to the SFU (`Connect`'s `transport_mode`); audio
+    /// always rides datagrams regardless.
+    transport_mode: quic::TransportMode,
+    video_capture: Option<VideoCapture>,
+    video_reassembler: quic::VideoReassembler,
+    jitter: jitter::JitterBuffer,
+    playout_ticker: tokio::time::Interval,
+    /// Playout ticks since the last `MediaEvent::CallStats` emission.
+    stats_tick_counter: u32,
+    /// Delay-based estimate of downlink capacity, fed by incoming video
+    /// datagrams and reported to the SFU over `feedback_stream`.
+    bwe: bwe::GccEstimator,
+    /// Dedicated stream for reporting `bwe` target bitrates to the SFU,
+    /// which uses them to pick this peer's simulcast layer.
+    feedback_stream: quinn::SendStream,
+}
+
+/// Live camera capture + per-layer encoders, held only while video is enabled.
+struct VideoCapture {
+    _handle: video::cpal_video::CaptureHandle,
+    frame_rx: mpsc::UnboundedReceiver<video::VideoFrame>,
+    encoders: Vec<codec::VideoEncoder>,
+    sequence: u32,
+    timestamp: u32,
+    frame_id: u16,
+    frames_since_keyframe: u32,
+    /// Monotonic id for this peer's keyframe-object streams (`TransportMode::Hybrid`
+    /// only), so the SFU can tell which of several in-flight keyframes for a
+    /// layer is the newest one worth keeping.
+    next_keyframe_group_id: u32,
 }
 
 /// Establish a QUIC connection and start the audio pipeline.
@@ -51,9 +98,11 @@ async fn establish_session(
     token: String,
     room_id: u32,
     user_id: u32,
-    cert_der: Option<Vec<u8>>,
-    idle_timeout_secs: u64,
-    datagram_buffer_size: usize,
+    trust: quic::TrustMode,
+    client_auth: Option<quic::ClientAuthCert>,
+    transport_tuning: quic::TransportTuning,
+    transport_mode: quic::TransportMode,
+    dtx_enabled: bool,
 ) -> Result<ActiveSession, Box<dyn std::error::Error>> {
     // Parse URL — strip optional quic:// prefix
     let addr_str = url
@@ -75,33 +124,44 @@ async fn establish_session(
     };
 
     // Create QUIC endpoint and connect
-    let mut client_config = quic::make_client_config(cert_der)?;
-
-    let mut transport = quinn::TransportConfig::default();
-    transport.max_idle_timeout(Some(
-        quinn::IdleTimeout::try_from(Duration::from_secs(idle_timeout_secs))
-            .map_err(|e| format!("Invalid idle timeout: {e}"))?,
-    ));
-    transport.datagram_receive_buffer_size(Some(datagram_buffer_size));
-    client_config.transport_config(Arc::new(transport));
+    let client_config = quic::make_client_config_with_transport(
+        trust,
+        quic::default_crypto_provider(),
+        &transport_tuning,
+        quic::key_log_from_env(),
+        client_auth,
+    )?;
 
     let mut endpoint = quinn::Endpoint::client("0.0.0.0:0".parse()?)?;
     endpoint.set_default_client_config(client_config);
 
     let connection = endpoint.connect(addr, &host)?.await?;
 
-    // Send auth token as first datagram (SFU protocol requirement)
-    connection.send_datagram(Bytes::from(token))?;
+    // Send the auth frame as the first datagram (SFU protocol requirement):
+    // the token plus this client's codec support, in priority order, so the
+    // SFU only relays codecs the receiver can actually decode.
+    let auth_frame = quic::encode_auth_frame(&token, &SUPPORTED_CODECS);
+    connection.send_datagram(auth_frame)?;
+
+    // Open a dedicated stream to report delay-based bandwidth estimates
+    // back to the SFU, so it can pick this peer's simulcast layer instead
+    // of blindly fanning out every layer. Its first byte identifies the
+    // stream as a feedback stream, distinguishing it from the keyframe
+    // object streams opened later in `Hybrid` transport mode.
+    let mut feedback_stream = connection.open_uni().await?;
+    feedback_stream
+        .write_all(&[quic::STREAM_KIND_FEEDBACK])
+        .await?;
 
     // Start audio capture (960 samples = 20ms at 48kHz)
-    let (capture_stream, capture_rx) = audio::start_capture(960)?;
+    let (capture_stream, capture_rx) = audio::start_capture(AUDIO_FRAME_SAMPLES)?;
 
     // Start audio playback
     let (playback_stream, playback_tx) = audio::start_playback()?;
 
-    // Create Opus encoder/decoder
-    let encoder = codec::OpusEncoder::new()?;
-    let decoder = codec::OpusDecoder::new()?;
+    // Create the negotiated audio codec. Only Opus exists today; a future
+    // low-complexity fallback would be selected here from `SUPPORTED_CODECS`.
+    let codec: Box<dyn codec::AudioCodec> = Box::new(codec::OpusCodec::new(dtx_enabled)?);
 
     Ok(ActiveSession {
         connection,
@@ -109,8 +169,7 @@ async fn establish_session(
         user_id,
         sequence: 0,
         timestamp: 0,
-        encoder,
-        decoder,
+        codec,
         _capture_stream: capture_stream,
         capture_rx,
         _playback_stream: playback_stream,
@@ -118,9 +177,58 @@ async fn establish_session(
         muted: false,
         deafened: false,
         video: false,
+        transport_mode,
+        video_capture: None,
+        video_reassembler: quic::VideoReassembler::new(),
+        jitter: jitter::JitterBuffer::new(),
+        playout_ticker: tokio::time::interval(Duration::from_millis(20)),
+        stats_tick_counter: 0,
+        bwe: bwe::GccEstimator::new(),
+        feedback_stream,
     })
 }
 
+/// Start camera capture and one `VideoEncoder` per simulcast layer.
+fn start_video(session: &mut ActiveSession) {
+    if session.video_capture.is_some() {
+        return;
+    }
+    let (handle, frame_rx) = match video::start_camera_capture() {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::warn!("Failed to start camera capture: {}", e);
+            return;
+        }
+    };
+    let encoders = video::SIMULCAST_LAYERS
+        .iter()
+        .filter_map(|layer| {
+            match codec::VideoEncoder::new(layer.width, layer.height, layer.target_bitrate_kbps) {
+                Ok(enc) => Some(enc),
+                Err(e) => {
+                    tracing::warn!("Failed to create video encoder for {}x{}: {}", layer.width, layer.height, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    session.video_capture = Some(VideoCapture {
+        _handle: handle,
+        frame_rx,
+        encoders,
+        sequence: 0,
+        timestamp: 0,
+        frame_id: 0,
+        frames_since_keyframe: 0,
+        next_keyframe_group_id: 0,
+    });
+}
+
+/// Stop camera capture, dropping the encoders and releasing the device.
+fn stop_video(session: &mut ActiveSession) {
+    session.video_capture = None;
+}
+
 /// Attempt to reconnect with exponential backoff.
 /// Returns `Some(session)` on success, `None` after all attempts exhausted.
 async fn reconnect_with_backoff(
@@ -138,9 +246,11 @@ async fn reconnect_with_backoff(
             params.token.clone(),
             params.room_id,
             params.user_id,
-            params.cert_der.clone(),
-            params.idle_timeout_secs,
-            params.datagram_buffer_size,
+            params.trust.clone(),
+            params.client_auth.clone(),
+            params.transport_tuning.clone(),
+            params.transport_mode,
+            params.dtx_enabled,
         ).await {
             Ok(s) => {
                 push_event(events, MediaEvent::Connected);
@@ -184,18 +294,27 @@ pub async fn run_media_loop(
                     cmd = cmd_rx.recv() => {
                         match cmd {
                             None => break,
-                            Some(MediaCommand::Connect { url, token, room_id, user_id, cert_der, idle_timeout_secs, datagram_buffer_size }) => {
+                            Some(MediaCommand::Connect { url, token, room_id, user_id, trust, client_auth, transport_tuning, transport_mode, dtx_enabled }) => {
                                 tracing::info!("Connecting to SFU at {}", url);
+                                let transport_mode: quic::TransportMode = match transport_mode.parse() {
+                                    Ok(m) => m,
+                                    Err(e) => {
+                                        push_event(&events, MediaEvent::ConnectFailed(e));
+                                        continue;
+                                    }
+                                };
                                 let params = ConnectParams {
                                     url: url.clone(),
                                     token: token.clone(),
                                     room_id,
                                     user_id,
-                                    cert_der: cert_der.clone(),
-                                    idle_timeout_secs,
-                                    datagram_buffer_size,
+                                    trust: trust.clone(),
+                                    client_auth: client_auth.clone(),
+                                    transport_tuning: transport_tuning.clone(),
+                                    transport_mode,
+                                    dtx_enabled,
                                 };
-                                match establish_session(url, token, room_id, user_id, cert_der, idle_timeout_secs, datagram_buffer_size).await {
+                                match establish_session(url, token, room_id, user_id, trust, client_auth, transport_tuning, transport_mode, dtx_enabled).await {
                                     Ok(s) => {
                                         tracing::info!("Connected to SFU");
                                         push_event(&events, MediaEvent::Connected);
@@ -226,20 +345,29 @@ pub async fn run_media_loop(
                     cmd = cmd_rx.recv() => {
                         match cmd {
                             None => break,
-                            Some(MediaCommand::Connect { url, token, room_id, user_id, cert_der, idle_timeout_secs, datagram_buffer_size }) => {
+                            Some(MediaCommand::Connect { url, token, room_id, user_id, trust, client_auth, transport_tuning, transport_mode, dtx_enabled }) => {
                                 tracing::info!("Reconnecting to SFU at {}", url);
                                 // Drop current session, then connect
                                 session = None;
+                                let transport_mode: quic::TransportMode = match transport_mode.parse() {
+                                    Ok(m) => m,
+                                    Err(e) => {
+                                        push_event(&events, MediaEvent::ConnectFailed(e));
+                                        continue;
+                                    }
+                                };
                                 let params = ConnectParams {
                                     url: url.clone(),
                                     token: token.clone(),
                                     room_id,
                                     user_id,
-                                    cert_der: cert_der.clone(),
-                                    idle_timeout_secs,
-                                    datagram_buffer_size,
+                                    trust: trust.clone(),
+                                    client_auth: client_auth.clone(),
+                                    transport_tuning: transport_tuning.clone(),
+                                    transport_mode,
+                                    dtx_enabled,
                                 };
-                                match establish_session(url, token, room_id, user_id, cert_der, idle_timeout_secs, datagram_buffer_size).await {
+                                match establish_session(url, token, room_id, user_id, trust, client_auth, transport_tuning, transport_mode, dtx_enabled).await {
                                     Ok(new_s) => {
                                         tracing::info!("Connected to SFU");
                                         push_event(&events, MediaEvent::Connected);
@@ -268,6 +396,11 @@ pub async fn run_media_loop(
                             }
                             Some(MediaCommand::SetVideo(enabled)) => {
                                 s.video = enabled;
+                                if enabled {
+                                    start_video(s);
+                                } else {
+                                    stop_video(s);
+                                }
                             }
                         }
                     }
@@ -276,12 +409,24 @@ pub async fn run_media_loop(
                             send_audio_frame(s, pcm);
                         }
                     }
+                    maybe_frame = async {
+                        match s.video_capture.as_mut() {
+                            Some(vc) => vc.frame_rx.recv().await,
+                            None => std::future::pending().await,
+                        }
+                    }, if s.video_capture.is_some() => {
+                        if let Some(frame) = maybe_frame {
+                            send_video_frame(s, frame).await;
+                        }
+                    }
+                    _ = s.playout_ticker.tick() => {
+                        play_next_audio_frame(s, &events);
+                    }
                     result = s.connection.read_datagram() => {
                         match result {
                             Ok(data) => {
-                                if !s.deafened {
-                                    receive_audio_frame(s, data);
-                                }
+                                receive_audio_frame(s, data.clone());
+                                receive_video_frame(s, data).await;
                             }
                             Err(e) => {
                                 tracing::error!("QUIC read error: {}", e);
@@ -307,34 +452,175 @@ pub async fn run_media_loop(
     }
 }
 
-/// Encode and send an audio frame over QUIC.
+/// Encode and send an audio frame over QUIC. With DTX enabled, libopus
+/// returns a fully empty packet during silence between comfort-noise
+/// updates — those ticks send nothing at all rather than an empty datagram.
+/// `sequence`/`timestamp` still advance every tick regardless, so the gap
+/// on the wire lines up with real elapsed time for the receiver's jitter
+/// buffer and the next comfort-noise update (flagged `dtx`) marks the gap
+/// as silence rather than loss.
 fn send_audio_frame(session: &mut ActiveSession, pcm: Vec<i16>) {
-    let opus_data = match session.encoder.encode(&pcm) {
+    let encoded = match session.codec.encode(&pcm) {
         Ok(data) => data,
         Err(e) => {
-            tracing::warn!("Opus encode error: {}", e);
+            tracing::warn!("audio encode error: {}", e);
             return;
         }
     };
 
-    let frame = quic::OutFrame::audio(
-        session.room_id,
-        session.user_id,
-        quic::CODEC_OPUS,
-        session.sequence,
-        session.timestamp,
-        opus_data,
-    );
+    if !encoded.is_empty() {
+        let dtx = session.codec.is_dtx_frame(&encoded);
+        let frame = quic::OutFrame::audio(
+            session.room_id,
+            session.user_id,
+            session.codec.codec_id(),
+            session.sequence,
+            session.timestamp,
+            session.codec.fec_enabled(),
+            dtx,
+            encoded,
+        );
 
-    if let Err(e) = session.connection.send_datagram(frame.encode()) {
-        tracing::warn!("Failed to send datagram: {}", e);
+        if let Err(e) = session.connection.send_datagram(frame.encode()) {
+            tracing::warn!("Failed to send datagram: {}", e);
+        }
     }
 
     session.sequence = session.sequence.wrapping_add(1);
-    session.timestamp = session.timestamp.wrapping_add(960);
+    session.timestamp = session.timestamp.wrapping_add(AUDIO_FRAME_SAMPLES as u32);
 }
 
-/// Decode and play back a received audio frame.
+/// Encode one captured camera frame into every simulcast layer and send
+/// each encoded chunk. In `TransportMode::Datagram`, every chunk (keyframes
+/// included) is fragmented across datagrams; in `TransportMode::Hybrid`,
+/// keyframes instead go out as their own reliable QUIC stream so a lost
+/// packet can't stall the next decodable point, while deltas stay on
+/// datagrams either way.
+async fn send_video_frame(session: &mut ActiveSession, frame: video::VideoFrame) {
+    let Some(vc) = session.video_capture.as_mut() else {
+        return;
+    };
+
+    // Force a keyframe on every layer roughly every 2s (at ~30fps) so late
+    // joiners and layer upgrades always have a decodable entry point.
+    let force_keyframe = vc.frames_since_keyframe == 0;
+    vc.frames_since_keyframe = (vc.frames_since_keyframe + 1) % 60;
+
+    for (layer_idx, encoder) in vc.encoders.iter_mut().enumerate() {
+        let encoded = match encoder.encode(&frame.data, vc.timestamp as i64, force_keyframe) {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::warn!("Video encode error on layer {}: {}", layer_idx, e);
+                continue;
+            }
+        };
+        if encoded.data.is_empty() {
+            continue;
+        }
+
+        if session.transport_mode == quic::TransportMode::Hybrid && encoded.is_keyframe {
+            let group_id = vc.next_keyframe_group_id;
+            vc.next_keyframe_group_id = vc.next_keyframe_group_id.wrapping_add(1);
+            let out_frame = quic::OutFrame::video(
+                session.room_id,
+                session.user_id,
+                quic::CODEC_VP9,
+                vc.sequence,
+                vc.timestamp,
+                layer_idx as u8,
+                true,
+                encoded.data,
+            );
+            vc.sequence = vc.sequence.wrapping_add(1);
+            send_keyframe_object(&session.connection, group_id, &out_frame).await;
+            continue;
+        }
+
+        let datagrams = quic::fragment_video_frame(
+            session.room_id,
+            session.user_id,
+            quic::CODEC_VP9,
+            vc.sequence,
+            vc.timestamp,
+            layer_idx as u8,
+            encoded.is_keyframe,
+            vc.frame_id,
+            &encoded.data,
+            MAX_DATAGRAM_PAYLOAD,
+        );
+        vc.sequence = vc.sequence.wrapping_add(datagrams.len() as u32);
+
+        for datagram in datagrams {
+            if let Err(e) = session.connection.send_datagram(datagram) {
+                tracing::warn!("Failed to send video datagram: {}", e);
+            }
+        }
+    }
+
+    vc.frame_id = vc.frame_id.wrapping_add(1);
+    vc.timestamp = vc.timestamp.wrapping_add(3000); // 90kHz timebase at ~30fps
+}
+
+/// Send one encoded keyframe chunk as its own unidirectional QUIC stream
+/// instead of fragmented datagrams, with priority set from its group id so
+/// the QUIC scheduler favors a newer keyframe over an older one still being
+/// sent. Used only in `TransportMode::Hybrid`.
+async fn send_keyframe_object(
+    connection: &quinn::Connection,
+    group_id: u32,
+    frame: &quic::OutFrame,
+) {
+    let object = quic::encode_keyframe_object(group_id, &frame.header, &frame.payload);
+    let mut send = match connection.open_uni().await {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!("Failed to open keyframe stream: {}", e);
+            return;
+        }
+    };
+    let _ = send.set_priority(group_id as i32);
+    if let Err(e) = send.write_all(&object).await {
+        tracing::warn!("Failed to send keyframe object: {}", e);
+        return;
+    }
+    let _ = send.finish();
+}
+
+/// Reassemble and (eventually) decode an incoming video datagram. Decoding
+/// is left to the Python layer's renderer once wired up; for now reassembly
+/// keeps the per-layer fragment state bounded. Also feeds the datagram's
+/// send timestamp into the bandwidth estimator and reports any fresh
+/// target bitrate to the SFU over the feedback stream.
+async fn receive_video_frame(session: &mut ActiveSession, data: Bytes) {
+    let frame = match quic::InFrame::decode(data) {
+        Some(f) => f,
+        None => return,
+    };
+    if frame.header.media_type != quic::MEDIA_TYPE_VIDEO {
+        return;
+    }
+
+    if let Some(target_bps) = session
+        .bwe
+        .on_video_datagram(frame.header.timestamp, Instant::now())
+    {
+        if let Err(e) = session
+            .feedback_stream
+            .write_all(&target_bps.to_be_bytes())
+            .await
+        {
+            tracing::debug!("failed to send bandwidth feedback: {}", e);
+        }
+    }
+
+    let _reassembled = session
+        .video_reassembler
+        .push(frame.header.user_id, frame.header.spatial_id, &frame);
+}
+
+/// Hand off a received audio frame to the jitter buffer, keyed by its
+/// sequence number and timestamp. Decoding happens later, at the fixed 20ms
+/// playout cadence, not here.
 fn receive_audio_frame(session: &mut ActiveSession, data: Bytes) {
     let frame = match quic::InFrame::decode(data) {
         Some(f) => f,
@@ -348,13 +634,48 @@ fn receive_audio_frame(session: &mut ActiveSession, data: Bytes) {
         return;
     }
 
-    let pcm = match session.decoder.decode(&frame.payload) {
+    session
+        .jitter
+        .insert(&frame.header, frame.payload, Instant::now());
+}
+
+/// Advance the jitter buffer by one 20ms slot and decode whatever it
+/// produces: the expected frame, an FEC-reconstructed frame, comfort
+/// silence for a DTX gap, or a concealment frame for a frame that's simply
+/// gone. Also periodically reports the buffer's call-quality counters.
+fn play_next_audio_frame(session: &mut ActiveSession, events: &EventQueue) {
+    session.stats_tick_counter += 1;
+    if session.stats_tick_counter >= STATS_EMIT_INTERVAL_TICKS {
+        session.stats_tick_counter = 0;
+        let stats = session.jitter.stats();
+        push_event(
+            events,
+            MediaEvent::CallStats {
+                buffered_frames: stats.buffered_frames,
+                jitter_ms: stats.jitter_ms,
+                lost: stats.lost,
+                late: stats.late,
+            },
+        );
+    }
+
+    let pcm = match session.jitter.tick() {
+        jitter::PlayoutAction::Play(payload) => session.codec.decode(&payload),
+        jitter::PlayoutAction::Fec(next_payload) => session.codec.decode_fec(&next_payload),
+        jitter::PlayoutAction::Silence => Ok(vec![0i16; AUDIO_FRAME_SAMPLES]),
+        jitter::PlayoutAction::Conceal => session.codec.decode_plc(),
+        jitter::PlayoutAction::Wait => return,
+    };
+
+    let pcm = match pcm {
         Ok(samples) => samples,
         Err(e) => {
-            tracing::warn!("Opus decode error: {}", e);
+            tracing::warn!("audio decode error: {}", e);
             return;
         }
     };
 
-    let _ = session.playback_tx.send(pcm);
+    if !session.deafened {
+        let _ = session.playback_tx.send(pcm);
+    }
 }
@@ -1,19 +1,110 @@
-//! Video capture stubs — camera support is planned for a future release.
+//! Camera capture and multi-layer (simulcast) video encoding.
+//!
+//! Mirrors the audio pipeline in `audio.rs`/`codec.rs`: a background capture
+//! thread hands off raw frames over an mpsc channel, and the caller drives
+//! encoding on its own schedule.
 
-/// Placeholder for video frame data.
+use std::sync::mpsc as std_mpsc;
+use tokio::sync::mpsc;
+
+/// A single quality layer produced per captured frame, highest resolution first.
+#[derive(Clone, Copy, Debug)]
+pub struct SimulcastLayer {
+    pub width: u32,
+    pub height: u32,
+    pub target_bitrate_kbps: u32,
+}
+
+/// Descending-quality simulcast ladder, matching the 720p/480p/360p split
+/// used by the moq-rs encode script.
+pub const SIMULCAST_LAYERS: [SimulcastLayer; 3] = [
+    SimulcastLayer { width: 1280, height: 720, target_bitrate_kbps: 2000 },
+    SimulcastLayer { width: 854, height: 480, target_bitrate_kbps: 800 },
+    SimulcastLayer { width: 640, height: 360, target_bitrate_kbps: 350 },
+];
+
+/// A captured camera frame, in I420 (planar YUV 4:2:0) as produced by `nokhwa`'s
+/// decoder path — this is the format our video codecs encode from.
 pub struct VideoFrame {
     pub width: u32,
     pub height: u32,
     pub data: Vec<u8>,
 }
 
-/// Stub: start camera capture. Currently unimplemented.
-pub fn start_camera_capture() -> Result<(), Box<dyn std::error::Error>> {
-    tracing::warn!("Video capture is not yet implemented");
-    Ok(())
+/// Start camera capture on a background OS thread, yielding `VideoFrame`s at
+/// the camera's native resolution/frame rate. Downscaling to each simulcast
+/// layer happens at encode time.
+pub fn start_camera_capture() -> Result<(cpal_video::CaptureHandle, mpsc::UnboundedReceiver<VideoFrame>), Box<dyn std::error::Error>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (ready_tx, ready_rx) = std_mpsc::channel();
+
+    let join = std::thread::spawn(move || {
+        use nokhwa::pixel_format::I420Format;
+        use nokhwa::utils::{RequestedFormat, RequestedFormatType};
+        use nokhwa::Camera;
+
+        let format = RequestedFormat::new::<I420Format>(RequestedFormatType::AbsoluteHighestFrameRate);
+        let mut camera = match Camera::new(nokhwa::utils::CameraIndex::Index(0), format) {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e.to_string()));
+                return;
+            }
+        };
+
+        if let Err(e) = camera.open_stream() {
+            let _ = ready_tx.send(Err(e.to_string()));
+            return;
+        }
+        let _ = ready_tx.send(Ok(()));
+
+        loop {
+            let frame = match camera.frame() {
+                Ok(f) => f,
+                Err(e) => {
+                    tracing::warn!("camera frame capture error: {}", e);
+                    break;
+                }
+            };
+            let resolution = frame.resolution();
+            let decoded = match frame.decode_image::<I420Format>() {
+                Ok(d) => d,
+                Err(e) => {
+                    tracing::warn!("camera frame decode error: {}", e);
+                    continue;
+                }
+            };
+            let vf = VideoFrame {
+                width: resolution.width(),
+                height: resolution.height(),
+                data: decoded.to_vec(),
+            };
+            if tx.send(vf).is_err() {
+                break;
+            }
+        }
+    });
+
+    match ready_rx.recv() {
+        Ok(Ok(())) => Ok((cpal_video::CaptureHandle { _join: join }, rx)),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Err("camera capture thread exited before starting".into()),
+    }
+}
+
+/// Stop camera capture. Dropping the `CaptureHandle` returned by
+/// `start_camera_capture` detaches the capture thread without joining it;
+/// the thread exits and releases the device once its next send finds the
+/// receiver closed.
+pub fn stop_camera_capture(_handle: cpal_video::CaptureHandle) {
+    // Dropping the handle is sufficient: the capture thread observes the
+    // receiver being closed on its next `tx.send` and exits.
 }
 
-/// Stub: stop camera capture.
-pub fn stop_camera_capture() {
-    // no-op
+/// Thin module so `CaptureHandle` has a stable path without pulling `nokhwa`
+/// types into the public API of `video`.
+pub mod cpal_video {
+    pub struct CaptureHandle {
+        pub(crate) _join: std::thread::JoinHandle<()>,
+    }
 }